@@ -0,0 +1,39 @@
+use alvm::assembler::program_parsers::program;
+use alvm::assembler::Assembler;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nom::types::CompleteStr;
+
+// a few thousand lines mixing every instruction form and labels
+fn large_source() -> String {
+    let mut source = String::new();
+    for i in 0..1000 {
+        source.push_str(&format!("label_{}: load $0 #{}\n", i, i));
+        source.push_str("add $0 $1 $2\n");
+        source.push_str("eq $1 $2\n");
+        source.push_str(&format!("load $3 @label_{}\n", i));
+        source.push_str("jmpe $3\n");
+    }
+    source.push_str("hlt\n");
+    source
+}
+
+fn parse(c: &mut Criterion) {
+    let source = large_source();
+    c.bench_function("parse_program", |b| {
+        b.iter(|| program(CompleteStr(black_box(&source))).unwrap())
+    });
+}
+
+fn assemble(c: &mut Criterion) {
+    let source = large_source();
+    c.bench_function("assemble_program", |b| {
+        b.iter(|| {
+            Assembler::default()
+                .assemble(black_box(&source), 0)
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, parse, assemble);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use alvm::assembler::Assembler;
+use alvm::vm::VM;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// counts $0 down to zero, three instructions per iteration
+const COUNTDOWN: &str = "load $0 #50000
+load $1 #1
+load $2 #0
+load $3 @loop
+loop: sub $0 $1 $0
+neq $0 $2
+jmpe $3
+hlt
+";
+
+fn run_countdown(program: &[u8], predecode: bool) -> i32 {
+    let mut vm = VM::default();
+    vm.set_predecode(predecode);
+    vm.add_bytes(program);
+    vm.run();
+    vm.registers[0]
+}
+
+fn dispatch(c: &mut Criterion) {
+    let program = Assembler::default().assemble(COUNTDOWN, 0).unwrap();
+    let mut group = c.benchmark_group("dispatch");
+    // the baseline runs each instruction through `Instruction::decode` on
+    // every step, as the VM does without the cache
+    group.bench_function("decode_each_step", |b| {
+        b.iter(|| run_countdown(black_box(&program), false))
+    });
+    group.bench_function("predecoded", |b| {
+        b.iter(|| run_countdown(black_box(&program), true))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+use alvm::assembler::Assembler;
+use alvm::vm::VM;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// arithmetic on every iteration of a 20000 step countdown
+const ARITHMETIC: &str = "load $0 #20000
+load $1 #1
+load $2 #0
+load $3 @loop
+load $4 #3
+loop: add $5 $4 $5
+mul $5 $4 $6
+sub $6 $5 $7
+div $7 $4 $8
+sub $0 $1 $0
+neq $0 $2
+jmpe $3
+hlt
+";
+
+// alternates between two branches on every iteration
+const BRANCHES: &str = "load $0 #20000
+load $1 #1
+load $2 #0
+load $5 @odd
+load $6 @next
+load $7 @loop
+loop: eq $10 $2
+jmpe $5
+load $10 #0
+jmp $6
+odd: load $10 #1
+add $11 $1 $11
+next: sub $0 $1 $0
+neq $0 $2
+jmpe $7
+hlt
+";
+
+fn bench_program(c: &mut Criterion, name: &str, source: &str) {
+    let program = Assembler::default().assemble(source, 0).unwrap();
+    let mut group = c.benchmark_group(name);
+    for &predecode in &[false, true] {
+        let id = if predecode {
+            "predecoded"
+        } else {
+            "decode_each_step"
+        };
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                let mut vm = VM::default();
+                vm.set_predecode(predecode);
+                vm.add_bytes(black_box(&program));
+                vm.run();
+                vm.registers[0]
+            })
+        });
+    }
+    group.finish();
+}
+
+fn arithmetic(c: &mut Criterion) {
+    bench_program(c, "arithmetic_loop", ARITHMETIC);
+}
+
+fn branches(c: &mut Criterion) {
+    bench_program(c, "branch_heavy", BRANCHES);
+}
+
+criterion_group!(benches, arithmetic, branches);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+use alvm::repl::REPL;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::io::{self, Cursor};
+
+// a session mixing assembly, which echoes each instruction, with commands
+fn session() -> String {
+    let mut script = String::new();
+    for i in 0..200 {
+        script.push_str(&format!("load $0 #{}\n", i));
+        script.push_str("add $0 $0 $1\n");
+        script.push_str(".registers $0-$1\n");
+        script.push_str(".pc\n");
+    }
+    script.push_str(".program\n.symbols\n");
+    script
+}
+
+fn commands(c: &mut Criterion) {
+    let script = session();
+    c.bench_function("repl_commands", |b| {
+        b.iter(|| {
+            let mut repl = REPL::with_output(Box::new(io::sink()));
+            repl.run_script(Cursor::new(black_box(&script)))
+        })
+    });
+}
+
+criterion_group!(benches, commands);
+criterion_main!(benches);
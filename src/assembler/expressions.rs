@@ -0,0 +1,417 @@
+use crate::assembler::symbols::SymbolTable;
+use crate::assembler::AssemblerError;
+use crate::instruction::Opcode;
+
+use nom::types::CompleteStr;
+use nom::*;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// An operand computed at assembly time, e.g. `BUF_SIZE * 4 + 1` or
+/// `@table + 8`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i32),
+    Constant(String),
+    Label(String),
+    Negate(Box<Expr>),
+    Binary {
+        op: Operator,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// True if the value is a label address, possibly with an offset added.
+    pub fn has_label(&self) -> bool {
+        match self {
+            Expr::Label(_) => true,
+            Expr::Negate(e) => e.has_label(),
+            Expr::Binary { lhs, rhs, .. } => lhs.has_label() || rhs.has_label(),
+            Expr::Number(_) | Expr::Constant(_) => false,
+        }
+    }
+
+    /// True if the value doesn't depend on any constant or label.
+    pub fn is_literal(&self) -> bool {
+        match self {
+            Expr::Number(_) => true,
+            Expr::Negate(e) => e.is_literal(),
+            Expr::Binary { lhs, rhs, .. } => lhs.is_literal() && rhs.is_literal(),
+            Expr::Constant(_) | Expr::Label(_) => false,
+        }
+    }
+
+    pub fn evaluate(&self, symbols: &SymbolTable) -> Result<i32, AssemblerError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Constant(name) => symbols
+                .constant(name)
+                .ok_or_else(|| AssemblerError::UnknownConstant { name: name.clone() }),
+            Expr::Label(name) => match symbols.value(name) {
+                Some(offset) => Ok(offset as i32),
+                None => Err(AssemblerError::UnknownLabel { name: name.clone() }),
+            },
+            Expr::Negate(e) => e
+                .evaluate(symbols)?
+                .checked_neg()
+                .ok_or(AssemblerError::ExpressionOverflow),
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = lhs.evaluate(symbols)?;
+                let rhs = rhs.evaluate(symbols)?;
+                let value = match op {
+                    Operator::Add => lhs.checked_add(rhs),
+                    Operator::Sub => lhs.checked_sub(rhs),
+                    Operator::Mul => lhs.checked_mul(rhs),
+                    Operator::Div if rhs == 0 => return Err(AssemblerError::DivisionByZero),
+                    Operator::Div => lhs.checked_div(rhs),
+                };
+                value.ok_or(AssemblerError::ExpressionOverflow)
+            }
+        }
+    }
+}
+
+/// An operand of an object file: `value`, plus the address the linker
+/// gives the module `local` times, plus the address of `import` if set.
+#[derive(Debug, PartialEq)]
+pub struct Relocatable {
+    pub value: i32,
+    pub local: i32,
+    pub import: Option<String>,
+}
+
+// `Relocatable` while it's being worked out, where an import may still
+// appear any number of times
+struct Terms {
+    value: i64,
+    local: i64,
+    imports: Vec<(String, i64)>,
+}
+
+impl Terms {
+    fn constant(value: i64) -> Terms {
+        Terms {
+            value,
+            local: 0,
+            imports: vec![],
+        }
+    }
+
+    fn is_constant(&self) -> bool {
+        self.local == 0 && self.imports.iter().all(|(_, count)| *count == 0)
+    }
+
+    fn scale(mut self, factor: i64) -> Result<Terms, AssemblerError> {
+        let overflow = || AssemblerError::ExpressionOverflow;
+        self.value = self.value.checked_mul(factor).ok_or_else(overflow)?;
+        self.local = self.local.checked_mul(factor).ok_or_else(overflow)?;
+        for (_, count) in &mut self.imports {
+            *count = count.checked_mul(factor).ok_or_else(overflow)?;
+        }
+        Ok(self)
+    }
+
+    fn add(mut self, other: Terms) -> Result<Terms, AssemblerError> {
+        let overflow = || AssemblerError::ExpressionOverflow;
+        self.value = self.value.checked_add(other.value).ok_or_else(overflow)?;
+        self.local = self.local.checked_add(other.local).ok_or_else(overflow)?;
+        for (name, count) in other.imports {
+            match self.imports.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, total)) => *total = total.checked_add(count).ok_or_else(overflow)?,
+                None => self.imports.push((name, count)),
+            }
+        }
+        Ok(self)
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression for an object file, where labels are
+    /// offsets from the start of the module and `imports` are unknown.
+    /// Only label plus or minus a constant can be fixed up by the linker.
+    pub fn relocatable(
+        &self,
+        symbols: &SymbolTable,
+        imports: &[String],
+    ) -> Result<Relocatable, AssemblerError> {
+        let terms = self.terms(symbols, imports)?;
+        let mut used = terms.imports.into_iter().filter(|(_, count)| *count != 0);
+        let import = match (used.next(), used.next()) {
+            (None, _) => None,
+            (Some((name, 1)), None) if terms.local == 0 => Some(name),
+            _ => return Err(AssemblerError::NotRelocatable),
+        };
+        let value = i32::try_from(terms.value).map_err(|_| AssemblerError::ExpressionOverflow)?;
+        let local = i32::try_from(terms.local).map_err(|_| AssemblerError::NotRelocatable)?;
+        Ok(Relocatable {
+            value,
+            local,
+            import,
+        })
+    }
+
+    fn terms(&self, symbols: &SymbolTable, imports: &[String]) -> Result<Terms, AssemblerError> {
+        match self {
+            Expr::Label(name) if imports.contains(name) => Ok(Terms {
+                value: 0,
+                local: 0,
+                imports: vec![(name.clone(), 1)],
+            }),
+            Expr::Label(name) => match symbols.value(name) {
+                Some(offset) => Ok(Terms {
+                    value: offset as i64,
+                    local: 1,
+                    imports: vec![],
+                }),
+                None => Err(AssemblerError::UnknownLabel { name: name.clone() }),
+            },
+            Expr::Number(_) | Expr::Constant(_) => {
+                Ok(Terms::constant(i64::from(self.evaluate(symbols)?)))
+            }
+            Expr::Negate(e) => e.terms(symbols, imports)?.scale(-1),
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = lhs.terms(symbols, imports)?;
+                let rhs = rhs.terms(symbols, imports)?;
+                match op {
+                    Operator::Add => lhs.add(rhs),
+                    Operator::Sub => lhs.add(rhs.scale(-1)?),
+                    Operator::Mul if lhs.is_constant() => rhs.scale(lhs.value),
+                    Operator::Mul if rhs.is_constant() => lhs.scale(rhs.value),
+                    Operator::Div if lhs.is_constant() && rhs.is_constant() => {
+                        match lhs.value.checked_div(rhs.value) {
+                            Some(value) => Ok(Terms::constant(value)),
+                            None if rhs.value == 0 => Err(AssemblerError::DivisionByZero),
+                            None => Err(AssemblerError::ExpressionOverflow),
+                        }
+                    }
+                    _ => Err(AssemblerError::NotRelocatable),
+                }
+            }
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// constant names can't start with a digit or shadow a mnemonic, so a bare
+// name is never mistaken for the next instruction
+fn is_constant_name(name: CompleteStr) -> bool {
+    !name.starts_with(|c: char| c.is_ascii_digit()) && Opcode::from(name) == Opcode::IGL
+}
+
+fn fold(lhs: Expr, (op, rhs): (char, Expr)) -> Expr {
+    let op = match op {
+        '+' => Operator::Add,
+        '-' => Operator::Sub,
+        '*' => Operator::Mul,
+        _ => Operator::Div,
+    };
+    Expr::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    }
+}
+
+named!(pub constant_name<CompleteStr, CompleteStr>,
+    verify!(take_while1!(is_name_char), is_constant_name)
+);
+
+named!(factor<CompleteStr, Expr>,
+    ws!(
+        alt!(
+            map_res!(digit, |d: CompleteStr| d.parse::<i32>().map(Expr::Number)) |
+            delimited!(char!('('), expression, char!(')')) |
+            preceded!(char!('-'), factor) => { |e| Expr::Negate(Box::new(e)) } |
+            preceded!(char!('@'), take_while1!(is_name_char)) => {
+                |name: CompleteStr| Expr::Label(name.to_string())
+            } |
+            terminated!(constant_name, not!(char!(':'))) => {
+                |name: CompleteStr| Expr::Constant(name.to_string())
+            }
+        )
+    )
+);
+
+named!(term<CompleteStr, Expr>,
+    do_parse!(
+        first: factor >>
+        value: fold_many0!(pair!(one_of!("*/"), factor), first, fold) >>
+        (value)
+    )
+);
+
+named!(pub expression<CompleteStr, Expr>,
+    do_parse!(
+        first: term >>
+        value: fold_many0!(pair!(one_of!("+-"), term), first, fold) >>
+        (value)
+    )
+);
+
+// an expression without a leading `#` must start with a name, so a stray
+// number is still a parse error
+named!(pub bare_expression<CompleteStr, Expr>,
+    preceded!(
+        peek!(preceded!(opt!(multispace), alt!(tag!("@") | constant_name))),
+        expression
+    )
+);
+
+// `.equ NAME value` or `.define NAME value`
+named!(pub constant_definition<CompleteStr, (CompleteStr, Expr)>,
+    ws!(
+        do_parse!(
+            alt!(tag!(".equ") | tag!(".define")) >>
+            name: constant_name >>
+            value: expression >>
+            eof!() >>
+            ((name, value))
+        )
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::symbols::{Constant, Symbol};
+
+    fn parse(input: &str) -> Expr {
+        let (rest, expr) = expression(CompleteStr(input)).unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        expr
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        assert_eq!(parse("42"), Expr::Number(42));
+        assert_eq!(
+            parse("1 + 2 * 3"),
+            fold(
+                Expr::Number(1),
+                ('+', fold(Expr::Number(2), ('*', Expr::Number(3))))
+            )
+        );
+        assert_eq!(
+            parse("@table + 8"),
+            fold(Expr::Label("table".to_string()), ('+', Expr::Number(8)))
+        );
+        assert_eq!(
+            parse("-(SIZE)"),
+            Expr::Negate(Box::new(Expr::Constant("SIZE".to_string())))
+        );
+
+        // stops before the next instruction or label
+        let result = expression(CompleteStr("N - 1 load $0"));
+        assert_eq!(result.unwrap().0, CompleteStr("load $0"));
+        let result = expression(CompleteStr("N\nloop: hlt"));
+        assert_eq!(result.unwrap().0, CompleteStr("loop: hlt"));
+        assert!(expression(CompleteStr("hlt")).is_err());
+        assert!(bare_expression(CompleteStr("4 + N")).is_err());
+    }
+
+    #[test]
+    fn test_parse_constant_definition() {
+        let result = constant_definition(CompleteStr(".equ BUF_SIZE 16"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), (CompleteStr("BUF_SIZE"), Expr::Number(16))))
+        );
+        let result = constant_definition(CompleteStr(".define LAST BUF_SIZE - 1"));
+        assert!(result.is_ok());
+        assert!(constant_definition(CompleteStr(".equ load 1")).is_err());
+        assert!(constant_definition(CompleteStr(".equ SIZE")).is_err());
+        assert!(constant_definition(CompleteStr(".equ SIZE 1 2")).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_expression() {
+        let mut symbols = SymbolTable::default();
+        symbols.add_symbol(Symbol {
+            name: "table".to_string(),
+            offset: 40,
+        });
+        symbols.add_constant(Constant {
+            name: "BUF_SIZE".to_string(),
+            value: 16,
+        });
+
+        let evaluate = |input: &str| parse(input).evaluate(&symbols);
+        assert_eq!(evaluate("(BUF_SIZE * 4 + 1)"), Ok(65));
+        assert_eq!(evaluate("@table + 8"), Ok(48));
+        assert_eq!(evaluate("BUF_SIZE - 1"), Ok(15));
+        assert_eq!(evaluate("10 - 2 - 3"), Ok(5));
+        assert_eq!(evaluate("7 / 2 * 2"), Ok(6));
+        assert_eq!(
+            evaluate("1 / (BUF_SIZE - 16)"),
+            Err(AssemblerError::DivisionByZero)
+        );
+        assert_eq!(
+            evaluate("65536 * 65536"),
+            Err(AssemblerError::ExpressionOverflow)
+        );
+        assert_eq!(
+            evaluate("MISSING"),
+            Err(AssemblerError::UnknownConstant {
+                name: "MISSING".to_string()
+            })
+        );
+        assert!(parse("@table + 8").has_label());
+        assert!(!parse("BUF_SIZE").has_label());
+        assert!(parse("-(3 * 4)").is_literal());
+    }
+
+    #[test]
+    fn test_relocatable_expression() {
+        let mut symbols = SymbolTable::default();
+        symbols.add_symbol(Symbol {
+            name: "table".to_string(),
+            offset: 40,
+        });
+        symbols.add_symbol(Symbol {
+            name: "end".to_string(),
+            offset: 52,
+        });
+        let imports = vec!["ext".to_string()];
+
+        let relocatable = |input: &str| parse(input).relocatable(&symbols, &imports);
+        let local = |value, local| {
+            Ok(Relocatable {
+                value,
+                local,
+                import: None,
+            })
+        };
+        assert_eq!(relocatable("@table + 8"), local(48, 1));
+        assert_eq!(relocatable("@end - @table"), local(12, 0));
+        assert_eq!(relocatable("2 * (@table + 1) - @table"), local(42, 1));
+        assert_eq!(
+            relocatable("@ext - 4"),
+            Ok(Relocatable {
+                value: -4,
+                local: 0,
+                import: Some("ext".to_string())
+            })
+        );
+        assert_eq!(
+            relocatable("@ext + @table"),
+            Err(AssemblerError::NotRelocatable)
+        );
+        assert_eq!(relocatable("@ext * 2"), Err(AssemblerError::NotRelocatable));
+        assert_eq!(
+            relocatable("@table / 2"),
+            Err(AssemblerError::NotRelocatable)
+        );
+    }
+}
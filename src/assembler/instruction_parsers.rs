@@ -1,14 +1,19 @@
+use crate::assembler::expressions::{Expr, Relocatable};
+use crate::assembler::label_parsers::label_declaration;
 use crate::assembler::opcode_parsers::opcode;
-use crate::assembler::operand_parsers::integer_operand;
-use crate::assembler::register_parsers::register;
-use crate::assembler::Token;
+use crate::assembler::operand_parsers::operand;
+use crate::assembler::register_parsers::any_register;
+use crate::assembler::symbols::SymbolTable;
+use crate::assembler::{AssemblerError, Token};
+use crate::instruction::{Instruction, Operand, OperandKind, INSTRUCTION_SIZE};
+use crate::linker::{Relocation, RelocationKind};
 
 use nom::types::CompleteStr;
 use nom::*;
 
 #[derive(Debug, PartialEq)]
 pub struct AssemblerInstruction {
-    label: Option<String>,
+    label: Option<Token>,
     opcode: Token,
     operand1: Option<Token>,
     operand2: Option<Token>,
@@ -17,11 +22,12 @@ pub struct AssemblerInstruction {
 
 named!(pub instruction_one<CompleteStr, AssemblerInstruction>,
     do_parse!(
+        l: opt!(label_declaration) >>
         o: opcode >>
         opt!(multispace) >>
         (
             AssemblerInstruction{
-                label: None,
+                label: l,
                 opcode: o,
                 operand1: None,
                 operand2: None,
@@ -33,12 +39,13 @@ named!(pub instruction_one<CompleteStr, AssemblerInstruction>,
 
 named!(pub instruction_two<CompleteStr, AssemblerInstruction>,
     do_parse!(
+        l: opt!(label_declaration) >>
         o: opcode >>
-        r: register >>
-        i: integer_operand >>
+        r: any_register >>
+        i: operand >>
         (
             AssemblerInstruction{
-                label: None,
+                label: l,
                 opcode: o,
                 operand1: Some(r),
                 operand2: Some(i),
@@ -48,10 +55,105 @@ named!(pub instruction_two<CompleteStr, AssemblerInstruction>,
     )
 );
 
+named!(pub instruction_three<CompleteStr, AssemblerInstruction>,
+    do_parse!(
+        l: opt!(label_declaration) >>
+        o: opcode >>
+        r1: any_register >>
+        r2: any_register >>
+        r3: any_register >>
+        (
+            AssemblerInstruction{
+                label: l,
+                opcode: o,
+                operand1: Some(r1),
+                operand2: Some(r2),
+                operand3: Some(r3)
+            }
+        )
+    )
+);
+
+named!(pub instruction_four<CompleteStr, AssemblerInstruction>,
+    do_parse!(
+        l: opt!(label_declaration) >>
+        o: opcode >>
+        r: any_register >>
+        (
+            AssemblerInstruction{
+                label: l,
+                opcode: o,
+                operand1: Some(r),
+                operand2: None,
+                operand3: None
+            }
+        )
+    )
+);
+
+named!(pub instruction_five<CompleteStr, AssemblerInstruction>,
+    do_parse!(
+        l: opt!(label_declaration) >>
+        o: opcode >>
+        r1: any_register >>
+        r2: any_register >>
+        (
+            AssemblerInstruction{
+                label: l,
+                opcode: o,
+                operand1: Some(r1),
+                operand2: Some(r2),
+                operand3: None
+            }
+        )
+    )
+);
+
+named!(pub instruction_six<CompleteStr, AssemblerInstruction>,
+    do_parse!(
+        l: opt!(label_declaration) >>
+        o: opcode >>
+        i: operand >>
+        (
+            AssemblerInstruction{
+                label: l,
+                opcode: o,
+                operand1: Some(i),
+                operand2: None,
+                operand3: None
+            }
+        )
+    )
+);
+
+named!(pub instruction_seven<CompleteStr, AssemblerInstruction>,
+    do_parse!(
+        l: opt!(label_declaration) >>
+        o: opcode >>
+        r1: any_register >>
+        r2: any_register >>
+        i: operand >>
+        (
+            AssemblerInstruction{
+                label: l,
+                opcode: o,
+                operand1: Some(r1),
+                operand2: Some(r2),
+                operand3: Some(i)
+            }
+        )
+    )
+);
+
 named!(pub instruction<CompleteStr, AssemblerInstruction>,
     do_parse!(
         ins: alt!(
+            instruction_three |
+            instruction_seven |
             instruction_two |
+            instruction_five |
+            instruction_four |
+            instruction_six |
             instruction_one
         ) >>
         (
@@ -61,43 +163,183 @@ named!(pub instruction<CompleteStr, AssemblerInstruction>,
 );
 
 impl AssemblerInstruction {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut results: Vec<u8> = vec![];
-        match self.opcode {
-            Token::Op { code } => {
-                results.push(code.into());
-            }
-            _ => {
-                println!("Non-opcode found in opcode field");
-                std::process::exit(1);
-            }
+    pub fn label_name(&self) -> Option<&str> {
+        match &self.label {
+            Some(Token::LabelDeclaration { name }) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Encodes the instruction, which will be placed at `position` in the
+    /// program.
+    pub fn to_bytes(
+        &self,
+        symbols: &SymbolTable,
+        position: usize,
+    ) -> Result<Vec<u8>, AssemblerError> {
+        Ok(self.instruction(symbols, position)?.encode()?)
+    }
+
+    /// The instruction with its labels and constants resolved, placed at
+    /// `position` in the program.
+    pub fn instruction(
+        &self,
+        symbols: &SymbolTable,
+        position: usize,
+    ) -> Result<Instruction, AssemblerError> {
+        let opcode = match self.opcode {
+            Token::Op { code } => code,
+            _ => return Err(AssemblerError::NonOpcodeInOpcodeField),
         };
+        let kinds = opcode.operands();
+        let operands = [&self.operand1, &self.operand2, &self.operand3]
+            .iter()
+            .copied()
+            .flatten()
+            .enumerate()
+            .map(|(i, token)| {
+                let relative = kinds.get(i) == Some(&OperandKind::Relative);
+                let operand =
+                    AssemblerInstruction::extract_operand(token, symbols, position, relative)?;
+                AssemblerInstruction::resolve_label(operand, symbols, position, relative)
+            })
+            .collect::<Result<Vec<Operand>, AssemblerError>>()?;
 
-        for operand in &[&self.operand1, &self.operand2, &self.operand3] {
-            if let Some(token) = operand {
-                AssemblerInstruction::extract_operhand(token, &mut results)
-            }
+        Ok(Instruction::new(opcode, operands))
+    }
+
+    /// Encodes the instruction for an object file, placed at `position`
+    /// from the start of its module. Fields holding an address the linker
+    /// decides, a label of the module or one of `imports`, are left for the
+    /// relocations returned alongside the bytes.
+    pub fn to_object_bytes(
+        &self,
+        symbols: &SymbolTable,
+        position: usize,
+        imports: &[String],
+    ) -> Result<(Vec<u8>, Vec<Relocation>), AssemblerError> {
+        let opcode = match self.opcode {
+            Token::Op { code } => code,
+            _ => return Err(AssemblerError::NonOpcodeInOpcodeField),
+        };
+        let kinds = opcode.operands();
+        let mut operands = vec![];
+        let mut relocations = vec![];
+        // the opcode takes the first byte
+        let mut field = position + 1;
+        let tokens = [&self.operand1, &self.operand2, &self.operand3];
+        for (i, token) in tokens.iter().copied().flatten().enumerate() {
+            let kind = kinds.get(i).copied();
+            let expr = match token {
+                Token::LabelUsage { name } => Expr::Label(name.clone()),
+                Token::Expression { expr } => expr.clone(),
+                _ => {
+                    let relative = kind == Some(OperandKind::Relative);
+                    let operand = Self::extract_operand(token, symbols, position, relative)?;
+                    operands.push(operand);
+                    field += kind.map_or(0, OperandKind::size);
+                    continue;
+                }
+            };
+            let value = expr.relocatable(symbols, imports)?;
+            let (operand, relocation) = Self::relocate(value, kind, position, field)?;
+            operands.push(operand);
+            relocations.extend(relocation);
+            field += kind.map_or(0, OperandKind::size);
         }
 
-        results
+        let bytes = Instruction::new(opcode, operands).encode()?;
+        Ok((bytes, relocations))
+    }
+
+    // the operand to encode for `value` in a field at `field`, and the
+    // relocation the linker has to apply to it
+    fn relocate(
+        value: Relocatable,
+        kind: Option<OperandKind>,
+        position: usize,
+        field: usize,
+    ) -> Result<(Operand, Option<Relocation>), AssemblerError> {
+        let relocation = |kind, symbol| Relocation {
+            offset: field,
+            kind,
+            symbol,
+            addend: value.value,
+        };
+        let end = (position + INSTRUCTION_SIZE) as i32;
+        match (kind, &value.import, value.local) {
+            (_, None, 0) => Ok((Operand::Imm32(value.value), None)),
+            // both ends of a jump within the module move together
+            (Some(OperandKind::Relative), None, 1) => Ok((Operand::Imm32(value.value - end), None)),
+            (Some(OperandKind::Relative), Some(symbol), _) => Ok((
+                Operand::Imm32(0),
+                Some(relocation(RelocationKind::Relative, Some(symbol.clone()))),
+            )),
+            (Some(OperandKind::Integer), Some(symbol), _) => Ok((
+                Operand::Imm32(0),
+                Some(relocation(RelocationKind::Absolute, Some(symbol.clone()))),
+            )),
+            (Some(OperandKind::Integer), None, 1) => Ok((
+                Operand::Imm32(value.value),
+                Some(relocation(RelocationKind::Absolute, None)),
+            )),
+            _ => Err(AssemblerError::NotRelocatable),
+        }
     }
 
-    fn extract_operhand(t: &Token, results: &mut Vec<u8>) {
+    // `relative` operands take addresses computed by expressions as offsets
+    // from the end of the instruction; labels are left for `resolve_label`
+    fn extract_operand(
+        t: &Token,
+        symbols: &SymbolTable,
+        position: usize,
+        relative: bool,
+    ) -> Result<Operand, AssemblerError> {
         match t {
-            Token::Register { reg_num } => {
-                results.push(*reg_num);
-            }
-            Token::IntegerOperand { value } => {
-                let converted = *value as u16;
-                let byte1 = converted;
-                let byte2 = converted >> 8;
-                results.push(byte2 as u8);
-                results.push(byte1 as u8);
+            Token::Register { reg_num } => Ok(Operand::Reg(*reg_num)),
+            Token::FloatRegister { reg_num } => Ok(Operand::FReg(*reg_num)),
+            Token::RegisterAlias { name } => match symbols.register(name) {
+                Some(register) => Ok(register.clone()),
+                None => Err(AssemblerError::UnknownRegister { name: name.clone() }),
+            },
+            Token::IntegerOperand { value } => Ok(Operand::Imm32(*value)),
+            Token::FloatOperand { value } => Ok(Operand::Float(*value)),
+            Token::Expression { expr } => {
+                let value = expr.evaluate(symbols)?;
+                // a jump to an address only once labels subtracted from
+                // each other have cancelled out, as in an object file
+                let local = expr.relocatable(symbols, &[]).map(|value| value.local);
+                if relative && local == Ok(1) {
+                    let end = position + INSTRUCTION_SIZE;
+                    Ok(Operand::Imm32(value - end as i32))
+                } else {
+                    Ok(Operand::Imm32(value))
+                }
             }
-            _ => {
-                println!("Opcode found in operand field");
-                std::process::exit(1);
+            Token::LabelUsage { name } => Ok(Operand::Label(name.clone())),
+            _ => Err(AssemblerError::NonOperandInOperandField),
+        }
+    }
+
+    // the address of a label operand, or for a `relative` one its offset
+    // from the end of the instruction
+    fn resolve_label(
+        operand: Operand,
+        symbols: &SymbolTable,
+        position: usize,
+        relative: bool,
+    ) -> Result<Operand, AssemblerError> {
+        let name = match operand {
+            Operand::Label(name) => name,
+            operand => return Ok(operand),
+        };
+        match symbols.value(&name) {
+            Some(offset) if relative => {
+                let end = position + INSTRUCTION_SIZE;
+                Ok(Operand::Imm32(offset as i32 - end as i32))
             }
+            Some(offset) => Ok(Operand::Imm16(offset as u16)),
+            None => Err(AssemblerError::UnknownLabel { name }),
         }
     }
 }
@@ -105,6 +347,7 @@ impl AssemblerInstruction {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::symbols::Symbol;
     use crate::instruction::Opcode;
 
     #[test]
@@ -142,4 +385,240 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_parse_instruction_form_three() {
+        let result = instruction_three(CompleteStr("add $0 $1 $2\n"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                AssemblerInstruction {
+                    label: None,
+                    opcode: Token::Op { code: Opcode::ADD },
+                    operand1: Some(Token::Register { reg_num: 0 }),
+                    operand2: Some(Token::Register { reg_num: 1 }),
+                    operand3: Some(Token::Register { reg_num: 2 })
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_form_four() {
+        let result = instruction_four(CompleteStr("jmp $3\n"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                AssemblerInstruction {
+                    label: None,
+                    opcode: Token::Op { code: Opcode::JMP },
+                    operand1: Some(Token::Register { reg_num: 3 }),
+                    operand2: None,
+                    operand3: None
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_instruction_to_bytes_errors() {
+        let symbols = SymbolTable::default();
+        let instruction = AssemblerInstruction {
+            label: None,
+            opcode: Token::Register { reg_num: 0 },
+            operand1: None,
+            operand2: None,
+            operand3: None,
+        };
+        assert_eq!(
+            instruction.to_bytes(&symbols, 0),
+            Err(AssemblerError::NonOpcodeInOpcodeField)
+        );
+
+        let instruction = AssemblerInstruction {
+            label: None,
+            opcode: Token::Op { code: Opcode::JMP },
+            operand1: Some(Token::Op { code: Opcode::HLT }),
+            operand2: None,
+            operand3: None,
+        };
+        assert_eq!(
+            instruction.to_bytes(&symbols, 0),
+            Err(AssemblerError::NonOperandInOperandField)
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_form_five() {
+        let result = instruction(CompleteStr("eq $0 $1\n"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                AssemblerInstruction {
+                    label: None,
+                    opcode: Token::Op { code: Opcode::EQ },
+                    operand1: Some(Token::Register { reg_num: 0 }),
+                    operand2: Some(Token::Register { reg_num: 1 }),
+                    operand3: None
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_instruction_to_bytes_pads() {
+        let symbols = SymbolTable::default();
+        let (_, hlt) = instruction(CompleteStr("hlt")).unwrap();
+        assert_eq!(hlt.to_bytes(&symbols, 0), Ok(vec![5, 0, 0, 0]));
+        let (_, jmp) = instruction(CompleteStr("jmp $2")).unwrap();
+        assert_eq!(jmp.to_bytes(&symbols, 0), Ok(vec![6, 2, 0, 0]));
+
+        let load = AssemblerInstruction {
+            label: None,
+            opcode: Token::Op { code: Opcode::LOAD },
+            operand1: Some(Token::Register { reg_num: 0 }),
+            operand2: Some(Token::IntegerOperand { value: 1 }),
+            operand3: Some(Token::IntegerOperand { value: 2 }),
+        };
+        assert_eq!(
+            load.to_bytes(&symbols, 0),
+            Err(AssemblerError::InvalidOperands {
+                mnemonic: "load",
+                syntax: "$a #n".to_string()
+            })
+        );
+
+        let (_, add) = instruction(CompleteStr("add $0 $1")).unwrap();
+        assert!(add.to_bytes(&symbols, 0).is_err());
+        let (_, igl) = instruction(CompleteStr("igl")).unwrap();
+        assert_eq!(
+            igl.to_bytes(&symbols, 0),
+            Err(AssemblerError::UnknownOpcode)
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_with_label() {
+        let result = instruction_two(CompleteStr("start: load $0 @start\n"));
+        let (rest, instruction) = result.unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(instruction.label_name(), Some("start"));
+    }
+
+    #[test]
+    fn test_parse_instruction_form_six() {
+        let result = instruction(CompleteStr("jmpr @loop\n"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                AssemblerInstruction {
+                    label: None,
+                    opcode: Token::Op { code: Opcode::JMPR },
+                    operand1: Some(Token::LabelUsage {
+                        name: "loop".to_string()
+                    }),
+                    operand2: None,
+                    operand3: None
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_form_seven() {
+        let (rest, addi) = instruction(CompleteStr("addi $0 $1 #5\nhlt")).unwrap();
+        assert_eq!(rest, CompleteStr("hlt"));
+        assert_eq!(
+            addi.to_bytes(&SymbolTable::default(), 0),
+            Ok(vec![38, 0, 1, 5])
+        );
+    }
+
+    #[test]
+    fn test_instruction_to_bytes_relative() {
+        let mut symbols = SymbolTable::default();
+        symbols.add_symbol(Symbol {
+            name: "loop".to_string(),
+            offset: 8,
+        });
+        let (_, jmpr) = instruction(CompleteStr("jmpr @loop")).unwrap();
+        assert_eq!(jmpr.to_bytes(&symbols, 20), Ok(vec![36, 255, 240, 0]));
+        assert_eq!(jmpr.to_bytes(&symbols, 0), Ok(vec![36, 0, 4, 0]));
+        let (_, jmpr) = instruction(CompleteStr("jmpre #-4")).unwrap();
+        assert_eq!(jmpr.to_bytes(&symbols, 0), Ok(vec![37, 255, 252, 0]));
+    }
+
+    #[test]
+    fn test_instruction_to_bytes_with_label() {
+        let mut symbols = SymbolTable::default();
+        symbols.add_symbol(Symbol {
+            name: "start".to_string(),
+            offset: 260,
+        });
+        let (_, instruction) = instruction_two(CompleteStr("load $1 @start")).unwrap();
+        assert_eq!(instruction.to_bytes(&symbols, 0), Ok(vec![0, 1, 1, 4]));
+
+        let (_, instruction) = instruction_two(CompleteStr("load $1 @end")).unwrap();
+        assert_eq!(
+            instruction.to_bytes(&symbols, 0),
+            Err(AssemblerError::UnknownLabel {
+                name: "end".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_instruction_to_object_bytes() {
+        let mut symbols = SymbolTable::default();
+        symbols.add_symbol(Symbol {
+            name: "loop".to_string(),
+            offset: 4,
+        });
+        let imports = vec!["ext".to_string()];
+        let encode = |source: &str, position| {
+            let (_, instruction) = instruction(CompleteStr(source)).unwrap();
+            instruction.to_object_bytes(&symbols, position, &imports)
+        };
+
+        assert_eq!(
+            encode("jmpr @loop", 12),
+            Ok((vec![36, 255, 244, 0], vec![]))
+        );
+        assert_eq!(
+            encode("load $2 @loop + 2", 0),
+            Ok((
+                vec![0, 2, 0, 6],
+                vec![Relocation {
+                    offset: 2,
+                    kind: RelocationKind::Absolute,
+                    symbol: None,
+                    addend: 6
+                }]
+            ))
+        );
+        assert_eq!(
+            encode("jmpre @ext", 8),
+            Ok((
+                vec![37, 0, 0, 0],
+                vec![Relocation {
+                    offset: 9,
+                    kind: RelocationKind::Relative,
+                    symbol: Some("ext".to_string()),
+                    addend: 0
+                }]
+            ))
+        );
+        assert_eq!(
+            encode("addi $0 $1 @loop", 0),
+            Err(AssemblerError::NotRelocatable)
+        );
+        assert_eq!(
+            encode("load $0 #(@ext - @loop)", 0),
+            Err(AssemblerError::NotRelocatable)
+        );
+    }
 }
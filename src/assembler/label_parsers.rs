@@ -0,0 +1,71 @@
+use crate::assembler::Token;
+
+use nom::types::CompleteStr;
+use nom::*;
+
+fn is_label_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+named!(pub label_declaration<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            name: take_while1!(is_label_char) >>
+            tag!(":") >>
+            (
+                Token::LabelDeclaration{name: name.to_string()}
+            )
+        )
+    )
+);
+
+named!(pub label_usage<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            tag!("@") >>
+            name: take_while1!(is_label_char) >>
+            (
+                Token::LabelUsage{name: name.to_string()}
+            )
+        )
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_label_declaration() {
+        let result = label_declaration(CompleteStr("loop_1: "));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                Token::LabelDeclaration {
+                    name: "loop_1".to_string()
+                }
+            ))
+        );
+
+        let result = label_declaration(CompleteStr("loop"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_label_usage() {
+        let result = label_usage(CompleteStr("@loop"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                Token::LabelUsage {
+                    name: "loop".to_string()
+                }
+            ))
+        );
+
+        let result = label_usage(CompleteStr("loop"));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::assembler::source::{Location, SourceLine};
+use crate::assembler::AssemblerError;
+use crate::instruction::Opcode;
+
+use nom::types::CompleteStr;
+use std::collections::HashMap;
+
+// deep enough for real nesting, shallow enough to catch a macro that
+// expands to itself
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+// likewise for macros that each invoke the next several times, whose output
+// grows exponentially with the depth
+const MAX_EXPANDED_LINES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<String>,
+}
+
+/// Macros defined so far, plus a counter that keeps the labels of every
+/// expansion unique for as long as the table lives.
+#[derive(Debug, Clone, Default)]
+pub struct MacroTable {
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    // body lines expanded by the current `expand` call
+    expanded_lines: usize,
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_label_char)
+}
+
+// a leading `name:` token, as the label parser would read it
+fn label_prefix(token: &str) -> Option<&str> {
+    token.strip_suffix(':').filter(|name| is_identifier(name))
+}
+
+impl MacroTable {
+    pub fn get(&self, name: &str) -> Option<&Macro> {
+        self.macros.get(name)
+    }
+
+    /// Collects `.macro` ... `.endm` definitions from `lines` and replaces
+    /// every invocation with the macro body, returning plain assembly.
+    /// Expanded lines keep the location of the invocation.
+    ///
+    /// Inside a body `\param` is replaced by the matching argument, and
+    /// labels declared in the body are renamed so each expansion gets its
+    /// own copy.
+    pub fn expand(&mut self, lines: Vec<SourceLine>) -> Result<Vec<SourceLine>, AssemblerError> {
+        let mut expanded = vec![];
+        let mut definition: Option<(Macro, Location)> = None;
+        self.expanded_lines = 0;
+        for line in lines {
+            let directive = line.text.split_whitespace().next().unwrap_or("");
+            let invalid = || AssemblerError::InvalidDirective {
+                line: line.text.clone(),
+            };
+            if let Some((mut current, start)) = definition.take() {
+                match directive {
+                    ".endm" => self.define(current).map_err(|e| start.locate(e))?,
+                    ".macro" => return Err(line.location.locate(invalid())),
+                    _ => {
+                        current.body.push(line.text);
+                        definition = Some((current, start));
+                    }
+                }
+                continue;
+            }
+            let result = match directive {
+                ".macro" => self.parse_definition(&line.text).map(|current| {
+                    definition = Some((current, line.location.clone()));
+                }),
+                ".endm" => Err(invalid()),
+                _ => self.expand_line(&line.text, &line.location, &mut expanded, 0),
+            };
+            result.map_err(|e| line.location.locate(e))?;
+        }
+
+        if let Some((current, start)) = definition {
+            let error = AssemblerError::UnterminatedMacro { name: current.name };
+            return Err(start.locate(error));
+        }
+        Ok(expanded)
+    }
+
+    fn parse_definition(&self, line: &str) -> Result<Macro, AssemblerError> {
+        let invalid = || AssemblerError::InvalidDirective {
+            line: line.to_string(),
+        };
+        let rest = line[".macro".len()..].trim();
+        let (name, params) = match rest.find(char::is_whitespace) {
+            Some(end) => (&rest[..end], rest[end..].trim()),
+            None => (rest, ""),
+        };
+        if !is_identifier(name) || Opcode::from(CompleteStr(name)) != Opcode::IGL {
+            return Err(invalid());
+        }
+
+        let mut names: Vec<String> = vec![];
+        if !params.is_empty() {
+            for param in params.split(',').map(str::trim) {
+                if !is_identifier(param) || names.iter().any(|p| p == param) {
+                    return Err(invalid());
+                }
+                names.push(param.to_string());
+            }
+        }
+        Ok(Macro {
+            name: name.to_string(),
+            params: names,
+            body: vec![],
+        })
+    }
+
+    fn define(&mut self, definition: Macro) -> Result<(), AssemblerError> {
+        if self.macros.contains_key(&definition.name) {
+            return Err(AssemblerError::DuplicateMacro {
+                name: definition.name,
+            });
+        }
+        self.macros.insert(definition.name.clone(), definition);
+        Ok(())
+    }
+
+    // appends `line` to `lines`, expanding it first if it invokes a macro
+    fn expand_line(
+        &mut self,
+        line: &str,
+        location: &Location,
+        lines: &mut Vec<SourceLine>,
+        depth: usize,
+    ) -> Result<(), AssemblerError> {
+        let source_line = |text: &str| SourceLine {
+            text: text.to_string(),
+            location: location.clone(),
+            expanded: depth > 0,
+        };
+        let mut rest = line;
+        let mut label = None;
+        if let Some(first) = rest.split_whitespace().next() {
+            if label_prefix(first).is_some() {
+                label = Some(first);
+                rest = rest[first.len()..].trim_start();
+            }
+        }
+        let name = rest.split_whitespace().next().unwrap_or("");
+        let definition = match self.macros.get(name) {
+            Some(definition) => definition.clone(),
+            None => {
+                lines.push(source_line(line));
+                return Ok(());
+            }
+        };
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(AssemblerError::MacroRecursion {
+                name: definition.name,
+            });
+        }
+
+        let args = rest[name.len()..].trim();
+        let args: Vec<&str> = if args.is_empty() {
+            vec![]
+        } else {
+            args.split(',').map(str::trim).collect()
+        };
+        if args.len() != definition.params.len() {
+            return Err(AssemblerError::MacroArguments {
+                name: definition.name,
+                expected: definition.params.len(),
+                found: args.len(),
+            });
+        }
+
+        // the label in front of an invocation marks its first instruction
+        if let Some(label) = label {
+            lines.push(source_line(label));
+        }
+        self.expansions += 1;
+        let suffix = format!("__{}", self.expansions);
+        let locals: Vec<&str> = definition
+            .body
+            .iter()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter_map(label_prefix)
+            .collect();
+        for body_line in &definition.body {
+            self.expanded_lines += 1;
+            if self.expanded_lines > MAX_EXPANDED_LINES {
+                return Err(AssemblerError::MacroExpansionLimit {
+                    limit: MAX_EXPANDED_LINES,
+                });
+            }
+            let body_line = substitute(body_line, &definition, &args, &locals, &suffix)?;
+            self.expand_line(&body_line, location, lines, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+// replaces `\param` with its argument and renames local labels, both where
+// they are declared and where they are used with `@`
+fn substitute(
+    line: &str,
+    definition: &Macro,
+    args: &[&str],
+    locals: &[&str],
+    suffix: &str,
+) -> Result<String, AssemblerError> {
+    let mut result = String::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if !is_label_char(c) && c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        while let Some(&(i, next)) = chars.peek() {
+            if !is_label_char(next) {
+                break;
+            }
+            end = i + next.len_utf8();
+            chars.next();
+        }
+        let word = &line[start..end];
+
+        if let Some(param) = word.strip_prefix('\\') {
+            match definition.params.iter().position(|p| p == param) {
+                Some(index) => result.push_str(args[index]),
+                None => {
+                    return Err(AssemblerError::UnknownMacroParameter {
+                        name: definition.name.clone(),
+                        param: param.to_string(),
+                    })
+                }
+            }
+            continue;
+        }
+        result.push_str(word);
+        let used = line[..start].ends_with('@');
+        let declared = line[end..].starts_with(':');
+        if (used || declared) && locals.contains(&word) {
+            result.push_str(suffix);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::source::source_lines;
+
+    fn expand(macros: &mut MacroTable, source: &str) -> Result<String, AssemblerError> {
+        let lines = macros.expand(source_lines(source, None))?;
+        let texts: Vec<String> = lines.into_iter().map(|line| line.text).collect();
+        Ok(texts.join("\n"))
+    }
+
+    #[test]
+    fn test_expand_parameters() {
+        let mut macros = MacroTable::default();
+        let source = ".macro loadc reg, value\nload \\reg \\value\n.endm\nloadc $1, #7\nhlt";
+        let expanded = expand(&mut macros, source).unwrap();
+        assert_eq!(expanded, "load $1 #7\nhlt");
+        assert_eq!(macros.get("loadc").unwrap().params, vec!["reg", "value"]);
+    }
+
+    #[test]
+    fn test_expand_local_labels() {
+        let mut macros = MacroTable::default();
+        let source = ".macro skip\njmpr @over\nover: hlt\n.endm\nstart: skip\nskip";
+        let expanded = expand(&mut macros, source).unwrap();
+        assert_eq!(
+            expanded,
+            "start:\njmpr @over__1\nover__1: hlt\njmpr @over__2\nover__2: hlt"
+        );
+    }
+
+    #[test]
+    fn test_expand_nested() {
+        let mut macros = MacroTable::default();
+        let source = ".macro one reg\nload \\reg #1\n.endm\n\
+                      .macro two a, b\none \\a\none \\b\n.endm\ntwo $0, $1";
+        let expanded = expand(&mut macros, source).unwrap();
+        assert_eq!(expanded, "load $0 #1\nload $1 #1");
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let mut macros = MacroTable::default();
+        assert_eq!(
+            expand(&mut macros, ".macro open\nhlt"),
+            Err(AssemblerError::UnterminatedMacro {
+                name: "open".to_string()
+            })
+        );
+        assert_eq!(
+            expand(&mut macros, ".macro load\n.endm"),
+            Err(AssemblerError::InvalidDirective {
+                line: ".macro load".to_string()
+            })
+        );
+        assert_eq!(
+            expand(&mut macros, ".endm"),
+            Err(AssemblerError::InvalidDirective {
+                line: ".endm".to_string()
+            })
+        );
+        assert_eq!(
+            expand(&mut macros, ".macro pair a, b\n.endm\npair $0"),
+            Err(AssemblerError::MacroArguments {
+                name: "pair".to_string(),
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            expand(&mut macros, ".macro pair a\n.endm"),
+            Err(AssemblerError::DuplicateMacro {
+                name: "pair".to_string()
+            })
+        );
+        assert_eq!(
+            expand(&mut macros, ".macro bad\nload \\reg #1\n.endm\nbad"),
+            Err(AssemblerError::UnknownMacroParameter {
+                name: "bad".to_string(),
+                param: "reg".to_string()
+            })
+        );
+        assert_eq!(
+            expand(&mut macros, ".macro forever\nforever\n.endm\nforever"),
+            Err(AssemblerError::MacroRecursion {
+                name: "forever".to_string()
+            })
+        );
+
+        // each macro invokes the one before twice, so the last doubles 22
+        // times over
+        let mut source = ".macro m0\nhlt\n.endm\n".to_string();
+        for i in 1..23 {
+            source += &format!(".macro m{}\nm{}\nm{}\n.endm\n", i, i - 1, i - 1);
+        }
+        source += "m22";
+        assert_eq!(
+            expand(&mut macros, &source),
+            Err(AssemblerError::MacroExpansionLimit {
+                limit: MAX_EXPANDED_LINES
+            })
+        );
+    }
+}
@@ -1,14 +1,891 @@
+pub mod expressions;
 pub mod instruction_parsers;
+pub mod label_parsers;
+pub mod macros;
 pub mod opcode_parsers;
 pub mod operand_parsers;
 pub mod program_parsers;
 pub mod register_parsers;
+pub mod source;
+pub mod symbols;
 
-use crate::instruction::Opcode;
+use crate::assembler::expressions::{constant_definition, Expr};
+use crate::assembler::macros::MacroTable;
+use crate::assembler::program_parsers::parse_program;
+use crate::assembler::register_parsers::{any_register, is_alias_name, REGISTER_ALIASES};
+use crate::assembler::source::{Includes, SourceLine};
+use crate::assembler::symbols::{Constant, RegisterAlias, Symbol, SymbolTable};
+use crate::debug::{DebugInfo, LineEntry};
+use crate::instruction::{EncodeError, Opcode, Operand, INSTRUCTION_SIZE, REGISTER_COUNT};
+use crate::linker::Object;
+
+use nom::types::CompleteStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
     Op { code: Opcode },
     Register { reg_num: u8 },
+    FloatRegister { reg_num: u8 },
+    RegisterAlias { name: String },
     IntegerOperand { value: i32 },
+    FloatOperand { value: f64 },
+    LabelDeclaration { name: String },
+    LabelUsage { name: String },
+    Expression { expr: Expr },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AssemblerError {
+    ParseError {
+        input: String,
+    },
+    DuplicateLabel {
+        name: String,
+    },
+    UnknownLabel {
+        name: String,
+    },
+    UnknownOpcode,
+    InvalidOperands {
+        mnemonic: &'static str,
+        syntax: String,
+    },
+    ImmediateOutOfRange {
+        value: i32,
+    },
+    InexactFloat {
+        value: f64,
+    },
+    // a float taking 64 bits that depends on a label placed after it
+    UnplacedFloat,
+    NonOpcodeInOpcodeField,
+    NonOperandInOperandField,
+    InvalidDirective {
+        line: String,
+    },
+    DuplicateMacro {
+        name: String,
+    },
+    UnterminatedMacro {
+        name: String,
+    },
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    UnknownMacroParameter {
+        name: String,
+        param: String,
+    },
+    MacroRecursion {
+        name: String,
+    },
+    MacroExpansionLimit {
+        limit: usize,
+    },
+    IncludeNotFound {
+        path: String,
+    },
+    IncludeCycle {
+        path: String,
+    },
+    IncludesDisabled,
+    UnreadableFile {
+        path: String,
+        message: String,
+    },
+    DuplicateConstant {
+        name: String,
+    },
+    UnknownConstant {
+        name: String,
+    },
+    DivisionByZero,
+    ExpressionOverflow,
+    InvalidRegister {
+        register: u8,
+    },
+    UnknownRegister {
+        name: String,
+    },
+    DuplicateRegister {
+        name: String,
+    },
+    // an address in an object that the linker can't compute
+    NotRelocatable,
+    // an error in a line read from a file
+    Located {
+        file: String,
+        line: usize,
+        error: Box<AssemblerError>,
+    },
+}
+
+impl From<EncodeError> for AssemblerError {
+    fn from(e: EncodeError) -> Self {
+        match e {
+            EncodeError::IllegalOpcode => AssemblerError::UnknownOpcode,
+            EncodeError::InvalidOperands { mnemonic, syntax } => {
+                AssemblerError::InvalidOperands { mnemonic, syntax }
+            }
+            EncodeError::ImmediateOutOfRange { value } => {
+                AssemblerError::ImmediateOutOfRange { value }
+            }
+            EncodeError::InexactFloat { value } => AssemblerError::InexactFloat { value },
+            EncodeError::UnresolvedLabel { name } => AssemblerError::UnknownLabel { name },
+            EncodeError::InvalidRegister { register } => {
+                AssemblerError::InvalidRegister { register }
+            }
+        }
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerError::ParseError { input } => write!(f, "Unable to parse input: {}", input),
+            AssemblerError::DuplicateLabel { name } => write!(f, "Label {} already defined", name),
+            AssemblerError::UnknownLabel { name } => write!(f, "Label {} is not defined", name),
+            AssemblerError::UnknownOpcode => write!(f, "Unknown opcode"),
+            AssemblerError::InvalidOperands { mnemonic, syntax } => {
+                write!(f, "Invalid operands, expected: {} {}", mnemonic, syntax)
+            }
+            AssemblerError::ImmediateOutOfRange { value } => {
+                write!(f, "Integer {} doesn't fit in 16 bits", value)
+            }
+            AssemblerError::InexactFloat { value } => {
+                write!(f, "Float {} can't be encoded exactly in 16 bits", value)
+            }
+            AssemblerError::UnplacedFloat => {
+                write!(f, "A float needing 64 bits can't use a later label")
+            }
+            AssemblerError::NonOpcodeInOpcodeField => write!(f, "Non-opcode found in opcode field"),
+            AssemblerError::NonOperandInOperandField => write!(f, "Opcode found in operand field"),
+            AssemblerError::InvalidDirective { line } => write!(f, "Invalid directive: {}", line),
+            AssemblerError::DuplicateMacro { name } => write!(f, "Macro {} already defined", name),
+            AssemblerError::UnterminatedMacro { name } => {
+                write!(f, "Macro {} is missing .endm", name)
+            }
+            AssemblerError::MacroArguments {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Macro {} takes {} arguments, found {}",
+                name, expected, found
+            ),
+            AssemblerError::UnknownMacroParameter { name, param } => {
+                write!(f, "Macro {} has no parameter {}", name, param)
+            }
+            AssemblerError::MacroRecursion { name } => {
+                write!(f, "Macro {} expands too deeply", name)
+            }
+            AssemblerError::MacroExpansionLimit { limit } => {
+                write!(f, "Macros expand to more than {} lines", limit)
+            }
+            AssemblerError::IncludeNotFound { path } => write!(f, "Include {} not found", path),
+            AssemblerError::IncludeCycle { path } => write!(f, "{} includes itself", path),
+            AssemblerError::IncludesDisabled => write!(f, "Including files is disabled"),
+            AssemblerError::UnreadableFile { path, message } => {
+                write!(f, "Unable to read {}: {}", path, message)
+            }
+            AssemblerError::DuplicateConstant { name } => {
+                write!(f, "Constant {} already defined", name)
+            }
+            AssemblerError::UnknownConstant { name } => {
+                write!(f, "Constant {} is not defined", name)
+            }
+            AssemblerError::DivisionByZero => write!(f, "Division by zero in expression"),
+            AssemblerError::ExpressionOverflow => write!(f, "Expression overflows 32 bits"),
+            AssemblerError::InvalidRegister { register } => {
+                write!(
+                    f,
+                    "Register {} doesn't exist, the last is {}",
+                    register,
+                    REGISTER_COUNT - 1
+                )
+            }
+            AssemblerError::UnknownRegister { name } => {
+                write!(f, "Register ${} is not defined", name)
+            }
+            AssemblerError::DuplicateRegister { name } => {
+                write!(f, "Register ${} already defined", name)
+            }
+            AssemblerError::NotRelocatable => {
+                write!(f, "Only a label plus or minus a constant can be linked")
+            }
+            AssemblerError::Located { file, line, error } => {
+                write!(f, "{}:{}: {}", file, line, error)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Assembler {
+    pub symbols: SymbolTable,
+    pub macros: MacroTable,
+    // searched in order for `.include` paths not found next to the source
+    pub include_dirs: Vec<PathBuf>,
+    // set for remote sessions, which must not read files on the host
+    pub deny_includes: bool,
+    // source positions and labels of everything `assemble` has produced
+    pub debug: DebugInfo,
+}
+
+impl Assembler {
+    /// Assembles `raw` as if its first byte will be placed at `offset` in
+    /// the VM program, so label addresses stay valid once appended.
+    /// Labels and macros from earlier calls remain visible.
+    pub fn assemble(&mut self, raw: &str, offset: usize) -> Result<Vec<u8>, AssemblerError> {
+        let lines = Includes::new(&self.include_dirs, !self.deny_includes).read(raw)?;
+        let object = self.assemble_lines(lines, offset, false)?;
+        Ok(object.code)
+    }
+
+    /// Like `assemble`, reading the source from `path`. Errors point at the
+    /// file and line they come from.
+    pub fn assemble_file(&mut self, path: &Path, offset: usize) -> Result<Vec<u8>, AssemblerError> {
+        let lines = Includes::new(&self.include_dirs, !self.deny_includes).read_file(path)?;
+        let object = self.assemble_lines(lines, offset, false)?;
+        Ok(object.code)
+    }
+
+    /// Assembles `raw` into an object for `linker::link`. Labels count from
+    /// the start of the object, `.export` names the ones other objects may
+    /// use and `.import` names the ones they provide. Meant for a fresh
+    /// assembler, since labels from earlier calls would be counted wrongly.
+    pub fn assemble_object(&mut self, raw: &str) -> Result<Object, AssemblerError> {
+        let lines = Includes::new(&self.include_dirs, !self.deny_includes).read(raw)?;
+        self.assemble_lines(lines, 0, true)
+    }
+
+    /// Like `assemble_object`, reading the source from `path`.
+    pub fn assemble_object_file(&mut self, path: &Path) -> Result<Object, AssemblerError> {
+        let lines = Includes::new(&self.include_dirs, !self.deny_includes).read_file(path)?;
+        self.assemble_lines(lines, 0, true)
+    }
+
+    // `relocatable` code leaves module addresses and imports to the linker;
+    // otherwise imports are resolved like any other label
+    fn assemble_lines(
+        &mut self,
+        lines: Vec<SourceLine>,
+        offset: usize,
+        relocatable: bool,
+    ) -> Result<Object, AssemblerError> {
+        // macros are expanded on a copy so a failed call defines nothing
+        let mut macros = self.macros.clone();
+        let lines = macros.expand(lines)?;
+
+        // likewise, labels and constants are only kept once everything
+        // assembled
+        let checkpoint = self.symbols.checkpoint();
+        let object = self.encode_lines(lines, offset, relocatable);
+        match &object {
+            Ok(object) => {
+                self.macros = macros;
+                if !relocatable {
+                    self.debug.append(&object.debug, 0);
+                }
+            }
+            Err(_) => self.symbols.restore(checkpoint),
+        }
+        object
+    }
+
+    fn encode_lines(
+        &mut self,
+        lines: Vec<SourceLine>,
+        offset: usize,
+        relocatable: bool,
+    ) -> Result<Object, AssemblerError> {
+        let mut program_lines = vec![];
+        let mut exports = vec![];
+        let mut imports: Vec<String> = vec![];
+        for line in lines {
+            match line.text.split_whitespace().next() {
+                Some(".export") => {
+                    let names = symbol_names(&line.text).map_err(|e| line.location.locate(e))?;
+                    exports.extend(names.into_iter().map(|name| (name, line.location.clone())));
+                }
+                Some(".import") => {
+                    let names = symbol_names(&line.text).map_err(|e| line.location.locate(e))?;
+                    for name in names {
+                        if !imports.contains(&name) {
+                            imports.push(name);
+                        }
+                    }
+                }
+                Some(".equ") | Some(".define") => self
+                    .define_constant(&line.text)
+                    .map_err(|e| line.location.locate(e))?,
+                Some(".reg") => self
+                    .define_register(&line.text)
+                    .map_err(|e| line.location.locate(e))?,
+                _ => program_lines.push(line),
+            }
+        }
+        let lines = program_lines;
+
+        let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        let source = texts.join("\n");
+        // the line holding the byte at `start` in `source`, and how far into
+        // its text that byte is
+        let line_at = |start: usize| {
+            let mut line_start = 0;
+            for line in &lines {
+                let end = line_start + line.text.len() + 1;
+                if start < end {
+                    return (line, start - line_start);
+                }
+                line_start = end;
+            }
+            unreachable!("offset is within the source")
+        };
+
+        let (program, starts) = parse_program(&source).map_err(|start| {
+            let input = source[start..].lines().next().unwrap_or("").trim();
+            let error = AssemblerError::ParseError {
+                input: input.to_string(),
+            };
+            line_at(start).0.location.locate(error)
+        })?;
+        let instructions = program.instructions().iter().zip(starts);
+
+        let mut object = Object::default();
+        let mut position = offset;
+        let mut sizes = vec![];
+        for (instruction, start) in instructions.clone() {
+            if let Some(name) = instruction.label_name() {
+                let imported = relocatable && imports.iter().any(|import| import == name);
+                if imported || self.symbols.has_symbol(name) {
+                    let error = AssemblerError::DuplicateLabel {
+                        name: name.to_string(),
+                    };
+                    return Err(line_at(start).0.location.locate(error));
+                }
+                let symbol = Symbol {
+                    name: name.to_string(),
+                    offset: position,
+                };
+                self.symbols.add_symbol(symbol.clone());
+                object.debug.labels.push(symbol);
+            }
+            // a LOADF64 of a float needing all 64 bits takes four
+            // instructions, known unless the float uses a later label
+            let size = instruction
+                .instruction(&self.symbols, position)
+                .map_or(INSTRUCTION_SIZE, |instruction| instruction.size());
+            sizes.push(size);
+            position += size;
+        }
+
+        // only labels of this input, since earlier ones aren't in the object
+        for (name, location) in exports {
+            let label = object.debug.labels.iter().find(|label| label.name == name);
+            match label.and_then(|label| label.offset.checked_sub(offset)) {
+                Some(offset) => object.exports.push(Symbol { name, offset }),
+                None => return Err(location.locate(AssemblerError::UnknownLabel { name })),
+            }
+        }
+        if relocatable {
+            object.imports = imports.clone();
+        } else {
+            imports.clear();
+        }
+
+        for ((instruction, start), size) in instructions.zip(sizes) {
+            let position = offset + object.code.len();
+            // the instruction is where its opcode is, which may be on the
+            // line after its label
+            let start = match instruction.label_name() {
+                Some(_) => {
+                    let colon = start + source[start..].find(':').expect("labels end in :") + 1;
+                    source.len() - source[colon..].trim_start().len()
+                }
+                None => start,
+            };
+            let (line, column) = line_at(start);
+            let location = &line.location;
+            // the lines of a macro all point at where it was invoked
+            let column = if line.expanded { 0 } else { column };
+            if let Some(file) = &location.file {
+                object.debug.lines.push(LineEntry {
+                    offset: position,
+                    file: file.clone(),
+                    line: location.line,
+                    column: location.column + column,
+                });
+            }
+            let (mut encoded, mut relocations) = if relocatable {
+                instruction.to_object_bytes(&self.symbols, position, &imports)
+            } else {
+                instruction
+                    .to_bytes(&self.symbols, position)
+                    .map(|bytes| (bytes, vec![]))
+            }
+            .map_err(|e| location.locate(e))?;
+            if encoded.len() != size {
+                return Err(location.locate(AssemblerError::UnplacedFloat));
+            }
+            object.code.append(&mut encoded);
+            object.relocations.append(&mut relocations);
+        }
+        Ok(object)
+    }
+
+    // constants may only use numbers and earlier constants, since labels
+    // aren't placed until the whole input has been read
+    fn define_constant(&mut self, line: &str) -> Result<(), AssemblerError> {
+        let invalid = || AssemblerError::InvalidDirective {
+            line: line.to_string(),
+        };
+        let (name, value) = match constant_definition(CompleteStr(line)) {
+            Ok((_, (name, value))) if !value.has_label() => (name.to_string(), value),
+            _ => return Err(invalid()),
+        };
+        if self.symbols.constant(&name).is_some() {
+            return Err(AssemblerError::DuplicateConstant { name });
+        }
+        let value = value.evaluate(&self.symbols)?;
+        self.symbols.add_constant(Constant { name, value });
+        Ok(())
+    }
+
+    // `.reg name $n`, where `$n` may itself be a name defined earlier
+    fn define_register(&mut self, line: &str) -> Result<(), AssemblerError> {
+        let invalid = || AssemblerError::InvalidDirective {
+            line: line.to_string(),
+        };
+        let mut words = line.split_whitespace().skip(1);
+        let (name, register) = match (words.next(), words.next(), words.next()) {
+            (Some(name), Some(register), None) => (name, register),
+            _ => return Err(invalid()),
+        };
+        let register = match any_register(CompleteStr(register)) {
+            Ok((rest, token)) if rest.is_empty() => token,
+            _ => return Err(invalid()),
+        };
+        let register = match register {
+            Token::Register { reg_num } => Operand::Reg(reg_num),
+            Token::FloatRegister { reg_num } => Operand::FReg(reg_num),
+            Token::RegisterAlias { name } => match self.symbols.register(&name) {
+                Some(register) => register.clone(),
+                None => return Err(AssemblerError::UnknownRegister { name }),
+            },
+            _ => return Err(invalid()),
+        };
+        if let Operand::Reg(number) | Operand::FReg(number) = register {
+            if usize::from(number) >= REGISTER_COUNT {
+                return Err(AssemblerError::InvalidRegister { register: number });
+            }
+        }
+
+        let name = name.trim_start_matches('$');
+        if !is_alias_name(name) {
+            return Err(invalid());
+        }
+        let builtin = REGISTER_ALIASES.iter().any(|(alias, _)| *alias == name);
+        if builtin || self.symbols.register(name).is_some() {
+            return Err(AssemblerError::DuplicateRegister {
+                name: name.to_string(),
+            });
+        }
+        self.symbols.add_register(RegisterAlias {
+            name: name.to_string(),
+            register,
+        });
+        Ok(())
+    }
+}
+
+// the names after `.export` or `.import`, separated by commas or spaces
+fn symbol_names(line: &str) -> Result<Vec<String>, AssemblerError> {
+    let names: Vec<String> = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .skip(1)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    let valid = |name: &String| name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if names.is_empty() || !names.iter().all(valid) {
+        return Err(AssemblerError::InvalidDirective {
+            line: line.to_string(),
+        });
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_assemble_program() {
+        let mut assembler = Assembler::default();
+        let bytes = assembler.assemble("load $0 #1\nload $1 #2\n", 0).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 1, 0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn test_assemble_register_instructions() {
+        let mut assembler = Assembler::default();
+        let bytes = assembler
+            .assemble("mov $0 $1\ninc $0\ndec $2\nneg $3\n", 0)
+            .unwrap();
+        assert_eq!(
+            bytes,
+            vec![44, 0, 1, 0, 45, 0, 0, 0, 46, 2, 0, 0, 47, 3, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_assemble_labels() {
+        let mut assembler = Assembler::default();
+        let bytes = assembler.assemble("load $0 @end\nend: hlt\n", 8).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 12, 5, 0, 0, 0]);
+        assert_eq!(assembler.symbols.value("end"), Some(12));
+
+        let result = assembler.assemble("end: hlt", 13);
+        assert_eq!(
+            result,
+            Err(AssemblerError::DuplicateLabel {
+                name: "end".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_assemble_wide_floats() {
+        let mut assembler = Assembler::default();
+        let bytes = assembler
+            .assemble("loadf64 $f0 #0.1\nafter: hlt\n", 0)
+            .unwrap();
+        assert_eq!(bytes.len(), 5 * INSTRUCTION_SIZE);
+        assert_eq!(bytes[..4], [48, 0, 0x3f, 0xb9]);
+        assert_eq!(assembler.symbols.value("after"), Some(16));
+
+        let result = assembler.assemble("loadf64 $f1 @later + 2049\nlater: hlt\n", 20);
+        assert_eq!(result, Err(AssemblerError::UnplacedFloat));
+    }
+
+    #[test]
+    fn test_relative_jump_over_wide_float() {
+        let mut assembler = Assembler::default();
+        let bytes = assembler
+            .assemble("jmpr @end\nloadf64 $f0 #0.1\nend: hlt\n", 0)
+            .unwrap();
+        assert_eq!(bytes[..4], [36, 0, 16, 0]);
+
+        let mut vm = VM::default();
+        vm.add_bytes(&bytes);
+        vm.run();
+        assert!(vm.halted());
+        assert_eq!(vm.float_registers[0], 0.0);
+    }
+
+    #[test]
+    fn test_assemble_forward_jump() {
+        let mut assembler = Assembler::default();
+        let bytes = assembler
+            .assemble("load $0 @end\njmp $0\nadd $1 $1 $1\nend: hlt\n", 0)
+            .unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 12, 6, 0, 0, 0, 1, 1, 1, 1, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let mut assembler = Assembler::default();
+        let result = assembler.assemble("start: load $0 @nowhere", 0);
+        assert_eq!(
+            result,
+            Err(AssemblerError::UnknownLabel {
+                name: "nowhere".to_string()
+            })
+        );
+        assert!(!assembler.symbols.has_symbol("start"));
+
+        let result = assembler.assemble("load $0 #1 $$", 0);
+        assert_eq!(
+            result,
+            Err(AssemblerError::ParseError {
+                input: "$$".to_string()
+            })
+        );
+
+        let result = assembler.assemble("load $0 #70000", 0);
+        assert_eq!(
+            result,
+            Err(AssemblerError::ImmediateOutOfRange { value: 70000 })
+        );
+    }
+
+    #[test]
+    fn test_assemble_file_locations() {
+        let dir = std::env::temp_dir().join(format!("alvm_assemble_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("main.asm"), "load $0 #1\n.include \"util.asm\"\n").unwrap();
+        std::fs::write(dir.join("lib/util.asm"), "inc $0\n\nload $0 @nowhere\n").unwrap();
+
+        let mut assembler = Assembler::default();
+        let error = assembler
+            .assemble_file(&dir.join("main.asm"), 0)
+            .unwrap_err();
+        let main = dir.join("main.asm");
+        assert_eq!(
+            error.to_string(),
+            format!("{}:2: Include util.asm not found", main.display())
+        );
+
+        assembler.include_dirs.push(dir.join("lib"));
+        let error = assembler
+            .assemble_file(&dir.join("main.asm"), 0)
+            .unwrap_err();
+        let util = dir.join("lib").join("util.asm");
+        assert_eq!(
+            error.to_string(),
+            format!("{}:3: Label nowhere is not defined", util.display())
+        );
+
+        std::fs::write(dir.join("lib/util.asm"), "top:\n  inc $0\n").unwrap();
+        let bytes = assembler.assemble_file(&dir.join("main.asm"), 0).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 1, 45, 0, 0, 0]);
+        assert_eq!(
+            assembler.debug.position(4),
+            format!("{}:2:3 (top)", util.display())
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_assemble_macro_locations() {
+        let dir = std::env::temp_dir().join(format!("alvm_macro_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.asm");
+        let source = ".macro twice reg\ninc \\reg\nagain: inc \\reg\n.endm\n  twice $1\n";
+        std::fs::write(&path, source).unwrap();
+
+        let mut assembler = Assembler::default();
+        assembler.assemble_file(&path, 0).unwrap();
+        let invocation = format!("{}:5:3", path.display());
+        assert_eq!(assembler.debug.line_at(0).unwrap().to_string(), invocation);
+        assert_eq!(assembler.debug.line_at(4).unwrap().to_string(), invocation);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_assemble_constants() {
+        let mut assembler = Assembler::default();
+        let source = ".equ BUF_SIZE 16\n\
+                      .define LAST BUF_SIZE - 1\n\
+                      load $0 #(BUF_SIZE * 4 + 1)\n\
+                      load $1 LAST\n\
+                      table: load $2 @table + 8\n\
+                      jmpr @table + 4\n";
+        let bytes = assembler.assemble(source, 0).unwrap();
+        assert_eq!(
+            bytes,
+            vec![0, 0, 0, 65, 0, 1, 0, 15, 0, 2, 0, 16, 36, 255, 252, 0]
+        );
+        assert_eq!(assembler.symbols.constant("LAST"), Some(15));
+
+        assert_eq!(
+            assembler.assemble(".equ BUF_SIZE 8", 16),
+            Err(AssemblerError::DuplicateConstant {
+                name: "BUF_SIZE".to_string()
+            })
+        );
+        assert_eq!(
+            assembler.assemble(".equ ADDR @table", 16),
+            Err(AssemblerError::InvalidDirective {
+                line: ".equ ADDR @table".to_string()
+            })
+        );
+        assert_eq!(
+            assembler.assemble(".equ HALF 8\nload $0 #(HALF / 0)", 16),
+            Err(AssemblerError::DivisionByZero)
+        );
+        assert_eq!(assembler.symbols.constant("HALF"), None);
+        assert_eq!(
+            assembler.assemble("load $0 MISSING", 16),
+            Err(AssemblerError::UnknownConstant {
+                name: "MISSING".to_string()
+            })
+        );
+
+        // the distance between two labels is an offset, not an address
+        let source = "a: hlt\nb: jmpr @b - @a\n";
+        let bytes = Assembler::default().assemble(source, 0).unwrap();
+        assert_eq!(bytes[4..], [36, 0, 4, 0]);
+        let object = Assembler::default().assemble_object(source).unwrap();
+        assert_eq!(object.code, bytes);
+    }
+
+    #[test]
+    fn test_assemble_register_names() {
+        let mut assembler = Assembler::default();
+        let source = ".reg count $t0\n\
+                      .reg total $count\n\
+                      .reg scale $f3\n\
+                      load $count #3\n\
+                      add $a0 $sp $total\n\
+                      loadf64 $scale #0.5\n";
+        let bytes = assembler.assemble(source, 0).unwrap();
+        assert_eq!(bytes, vec![0, 8, 0, 3, 1, 4, 29, 8, 16, 3, 56, 0]);
+
+        assert_eq!(
+            assembler.assemble("inc $32", 12),
+            Err(AssemblerError::InvalidRegister { register: 32 })
+        );
+        assert_eq!(
+            assembler.assemble(".reg big $40", 12),
+            Err(AssemblerError::InvalidRegister { register: 40 })
+        );
+        assert_eq!(
+            assembler.assemble("inc $missing", 12),
+            Err(AssemblerError::UnknownRegister {
+                name: "missing".to_string()
+            })
+        );
+        for name in ["count", "sp"] {
+            assert_eq!(
+                assembler.assemble(&format!(".reg {} $1", name), 12),
+                Err(AssemblerError::DuplicateRegister {
+                    name: name.to_string()
+                })
+            );
+        }
+        assert_eq!(
+            assembler.assemble(".reg f2 $1", 12),
+            Err(AssemblerError::InvalidDirective {
+                line: ".reg f2 $1".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_assemble_macros() {
+        let mut assembler = Assembler::default();
+        let source = ".macro skip_if_equal a, b\n\
+                      eq \\a \\b\n\
+                      jmpre @skip\n\
+                      load $9 #1\n\
+                      skip: inc $8\n\
+                      .endm\n";
+        assert_eq!(assembler.assemble(source, 0), Ok(vec![]));
+
+        let bytes = assembler
+            .assemble("start: skip_if_equal $0, $1\nskip_if_equal $2, $3\n", 0)
+            .unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(assembler.symbols.value("start"), Some(0));
+        assert_eq!(assembler.symbols.value("skip__1"), Some(12));
+        assert_eq!(assembler.symbols.value("skip__2"), Some(28));
+
+        // a failed call leaves neither labels nor macros behind
+        let result = assembler.assemble(".macro broken\n.endm\nbroken\nload $0 @nowhere", 32);
+        assert!(result.is_err());
+        assert!(assembler.macros.get("broken").is_none());
+    }
+
+    #[test]
+    fn test_assemble_object() {
+        use crate::linker::{Relocation, RelocationKind};
+
+        let mut assembler = Assembler::default();
+        let source = ".import print\n\
+                      .export start, data\n\
+                      start: load $0 @data\n\
+                      jmpr @print\n\
+                      data: hlt\n\
+                      jmpr @start\n";
+        let object = assembler.assemble_object(source).unwrap();
+        assert_eq!(
+            object.code,
+            vec![0, 0, 0, 8, 36, 0, 0, 0, 5, 0, 0, 0, 36, 255, 240, 0]
+        );
+        assert_eq!(
+            object.exports,
+            vec![
+                Symbol {
+                    name: "start".to_string(),
+                    offset: 0
+                },
+                Symbol {
+                    name: "data".to_string(),
+                    offset: 8
+                }
+            ]
+        );
+        assert_eq!(object.imports, vec!["print".to_string()]);
+        assert_eq!(
+            object.relocations,
+            vec![
+                Relocation {
+                    offset: 2,
+                    kind: RelocationKind::Absolute,
+                    symbol: None,
+                    addend: 8
+                },
+                Relocation {
+                    offset: 5,
+                    kind: RelocationKind::Relative,
+                    symbol: Some("print".to_string()),
+                    addend: 0
+                }
+            ]
+        );
+
+        // a whole program resolves imports like any other label
+        let bytes = Assembler::default()
+            .assemble(".import far\njmpr @far\nfar: hlt", 0)
+            .unwrap();
+        assert_eq!(bytes, vec![36, 0, 0, 0, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_assemble_object_errors() {
+        let object = |source: &str| Assembler::default().assemble_object(source);
+        assert_eq!(
+            object(".export missing\nhlt"),
+            Err(AssemblerError::UnknownLabel {
+                name: "missing".to_string()
+            })
+        );
+        assert_eq!(
+            object(".import start\nstart: hlt"),
+            Err(AssemblerError::DuplicateLabel {
+                name: "start".to_string()
+            })
+        );
+        assert_eq!(
+            object(".export"),
+            Err(AssemblerError::InvalidDirective {
+                line: ".export".to_string()
+            })
+        );
+
+        // labels from an earlier call lie outside the new code
+        let mut assembler = Assembler::default();
+        assembler.assemble("a: hlt", 0).unwrap();
+        assert_eq!(
+            assembler.assemble(".export a\nhlt", 4),
+            Err(AssemblerError::UnknownLabel {
+                name: "a".to_string()
+            })
+        );
+
+        let error = object("start: load $0 #(@start * 2)").unwrap_err();
+        assert_eq!(error, AssemblerError::NotRelocatable);
+        assert_eq!(
+            error.to_string(),
+            "Only a label plus or minus a constant can be linked"
+        );
+    }
 }
@@ -6,7 +6,7 @@ use nom::*;
 
 named!(pub opcode<CompleteStr, Token>,
     do_parse!(
-        opcode: alpha1 >>
+        opcode: alphanumeric1 >>
         (
             Token::Op{code: Opcode::from(opcode)}
         )
@@ -18,6 +18,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_opcode_load() {
         let result = opcode(CompleteStr("load"));
         assert_eq!(result.is_ok(), true);
@@ -1,20 +1,77 @@
+use crate::assembler::expressions::{bare_expression, expression, Expr};
+use crate::assembler::symbols::SymbolTable;
 use crate::assembler::Token;
 
 use nom::types::CompleteStr;
 use nom::*;
 
+// literal arithmetic like `#-8` or `#(4 * 4)` is folded right away, anything
+// naming a constant or label waits until the symbols are known
+fn integer_token(expr: Expr) -> Token {
+    if expr.is_literal() {
+        if let Ok(value) = expr.evaluate(&SymbolTable::default()) {
+            return Token::IntegerOperand { value };
+        }
+    }
+    Token::Expression { expr }
+}
+
 named!(pub integer_operand<CompleteStr, Token>,
     ws!(
         do_parse!(
             tag!("#") >>
-            reg_num: digit >>
+            expr: expression >>
+            (
+                integer_token(expr)
+            )
+        )
+    )
+);
+
+named!(exponent<CompleteStr, CompleteStr>,
+    recognize!(tuple!(one_of!("eE"), opt!(one_of!("+-")), digit))
+);
+
+// a float needs a fraction or an exponent, like `1.5`, `-.5` or `2e10`,
+// since anything else is an integer
+named!(float_literal<CompleteStr, CompleteStr>,
+    recognize!(tuple!(
+        opt!(tag!("-")),
+        alt!(
+            recognize!(tuple!(digit, tag!("."), digit, opt!(complete!(exponent)))) |
+            recognize!(tuple!(tag!("."), digit, opt!(complete!(exponent)))) |
+            recognize!(tuple!(digit, exponent))
+        )
+    ))
+);
+
+named!(pub float_operand<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            tag!("#") >>
+            value: map_res!(float_literal, |d: CompleteStr| d.parse::<f64>()) >>
             (
-                Token::IntegerOperand{value: reg_num.parse::<i32>().unwrap()}
+                Token::FloatOperand{value}
             )
         )
     )
 );
 
+named!(pub expression_operand<CompleteStr, Token>,
+    map!(bare_expression, |expr| match expr {
+        Expr::Label(name) => Token::LabelUsage{name},
+        expr => Token::Expression{expr},
+    })
+);
+
+named!(pub operand<CompleteStr, Token>,
+    alt!(
+        float_operand |
+        integer_operand |
+        expression_operand
+    )
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -22,12 +79,93 @@ mod tests {
     #[test]
     fn test_parse_integer_operhand() {
         let result = integer_operand(CompleteStr("#10"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (rest, value) = result.unwrap();
         assert_eq!(rest, CompleteStr(""));
         assert_eq!(value, Token::IntegerOperand { value: 10 });
 
         let result = integer_operand(CompleteStr("10"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
+
+        let result = integer_operand(CompleteStr("#99999999999"));
+        assert!(result.is_err());
+
+        let result = integer_operand(CompleteStr("#-8"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::IntegerOperand { value: -8 }))
+        );
+
+        let result = integer_operand(CompleteStr("#(3 * 4 + 1)"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::IntegerOperand { value: 13 }))
+        );
+    }
+
+    #[test]
+    fn test_parse_expression_operand() {
+        let result = operand(CompleteStr("#(SIZE * 4)"));
+        assert!(matches!(result, Ok((_, Token::Expression { .. }))));
+
+        let result = operand(CompleteStr("@table + 8"));
+        assert!(matches!(result, Ok((_, Token::Expression { .. }))));
+
+        let result = operand(CompleteStr("SIZE - 1"));
+        assert!(matches!(result, Ok((_, Token::Expression { .. }))));
+
+        assert!(operand(CompleteStr("4 + SIZE")).is_err());
+        assert!(operand(CompleteStr("hlt")).is_err());
+    }
+
+    #[test]
+    fn test_parse_float_operand() {
+        let result = float_operand(CompleteStr("#1.5"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::FloatOperand { value: 1.5 }))
+        );
+        assert!(float_operand(CompleteStr("#1")).is_err());
+        assert!(float_operand(CompleteStr("#1.")).is_err());
+
+        for (input, value) in [
+            ("#-1.5", -1.5),
+            ("#.5", 0.5),
+            ("#-.25", -0.25),
+            ("#2e3", 2000.0),
+            ("#1.5E-2", 0.015),
+            ("#2.75", 2.75),
+        ] {
+            let result = float_operand(CompleteStr(input));
+            assert_eq!(result, Ok((CompleteStr(""), Token::FloatOperand { value })));
+        }
+        assert!(float_operand(CompleteStr("#-8")).is_err());
+        assert!(float_operand(CompleteStr("#2e")).is_err());
+    }
+
+    #[test]
+    fn test_parse_operand() {
+        let result = operand(CompleteStr("#0.25"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::FloatOperand { value: 0.25 }))
+        );
+
+        let result = operand(CompleteStr("#10"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::IntegerOperand { value: 10 }))
+        );
+
+        let result = operand(CompleteStr("@end"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                Token::LabelUsage {
+                    name: "end".to_string()
+                }
+            ))
+        );
     }
 }
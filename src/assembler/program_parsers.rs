@@ -1,4 +1,6 @@
 use crate::assembler::instruction_parsers::{instruction, AssemblerInstruction};
+use crate::assembler::symbols::SymbolTable;
+use crate::assembler::AssemblerError;
 use nom::types::CompleteStr;
 use nom::*;
 
@@ -12,20 +14,54 @@ named!(pub program<CompleteStr, Program>,
         instructions: many1!(instruction) >>
         (
             Program {
-                instructions: instructions
+                instructions
             }
         )
     )
 );
 
+/// Parses `source` like `program`, one instruction at a time, also returning
+/// the byte offset where each instruction starts. On failure, returns the
+/// offset of the input that couldn't be parsed.
+pub fn parse_program(source: &str) -> Result<(Program, Vec<usize>), usize> {
+    let mut instructions = vec![];
+    let mut offsets = vec![];
+    let mut input = CompleteStr(source);
+    loop {
+        let start = source.len() - input.trim_start().len();
+        if start == source.len() {
+            break;
+        }
+        match instruction(input) {
+            Ok((rest, parsed)) => {
+                instructions.push(parsed);
+                offsets.push(start);
+                input = rest;
+            }
+            Err(_) => return Err(start),
+        }
+    }
+    Ok((Program { instructions }, offsets))
+}
+
 impl Program {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn instructions(&self) -> &[AssemblerInstruction] {
+        &self.instructions
+    }
+
+    /// Encodes the program as if its first byte will be placed at `offset`.
+    pub fn to_bytes(
+        &self,
+        symbols: &SymbolTable,
+        offset: usize,
+    ) -> Result<Vec<u8>, AssemblerError> {
         let mut program = vec![];
         for instruction in &self.instructions {
-            program.append(&mut instruction.to_bytes());
+            let position = offset + program.len();
+            program.append(&mut instruction.to_bytes(symbols, position)?);
         }
 
-        program
+        Ok(program)
     }
 }
 
@@ -34,6 +70,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_parse_program() {
         let result = program(CompleteStr("load $0 #100\n"));
         assert_eq!(result.is_ok(), true);
@@ -42,12 +79,22 @@ mod tests {
         assert_eq!(1, p.instructions.len());
     }
 
+    #[test]
+    fn test_parse_program_offsets() {
+        let source = "load $0 #100\n  add $0 $0 $0\nhlt";
+        let (p, offsets) = parse_program(source).unwrap();
+        assert_eq!(p.instructions.len(), 3);
+        assert_eq!(offsets, vec![0, 15, 28]);
+        assert_eq!(parse_program("hlt\nload $0 #1 $$"), Err(15));
+        assert_eq!(parse_program(" \n").unwrap().1, Vec::<usize>::new());
+    }
+
     #[test]
     fn test_program_to_bytes() {
         let result = program(CompleteStr("load $0 #200\n"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (_, program) = result.unwrap();
-        let bytecode = program.to_bytes();
+        let bytecode = program.to_bytes(&SymbolTable::default(), 0).unwrap();
         assert_eq!(bytecode.len(), 4);
     }
 }
@@ -3,20 +3,90 @@ use crate::assembler::Token;
 use nom::types::CompleteStr;
 use nom::*;
 
+/// ABI names for integer registers, numbered as on MIPS.
+pub const REGISTER_ALIASES: &[(&str, u8)] = &[
+    ("a0", 4),
+    ("a1", 5),
+    ("a2", 6),
+    ("a3", 7),
+    ("t0", 8),
+    ("t1", 9),
+    ("t2", 10),
+    ("t3", 11),
+    ("t4", 12),
+    ("t5", 13),
+    ("t6", 14),
+    ("t7", 15),
+    ("t8", 24),
+    ("t9", 25),
+    ("sp", 29),
+    ("fp", 30),
+    ("ra", 31),
+];
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// True if `name` could be given to a register with `.reg`: it must start
+/// with a letter and can't look like a float register.
+pub fn is_alias_name(name: &str) -> bool {
+    let float =
+        name.len() > 1 && name.starts_with('f') && name[1..].chars().all(|c| c.is_ascii_digit());
+    name.starts_with(char::is_alphabetic) && name.chars().all(is_name_char) && !float
+}
+
+// ABI names resolve right away, `.reg` names once the symbols are known
+fn named_register(name: CompleteStr) -> Token {
+    match REGISTER_ALIASES.iter().find(|(alias, _)| *alias == name.0) {
+        Some((_, reg_num)) => Token::Register { reg_num: *reg_num },
+        None => Token::RegisterAlias {
+            name: name.to_string(),
+        },
+    }
+}
+
 named!(pub register<CompleteStr, Token>,
     ws!(
         do_parse!(
             tag!("$") >>
-            reg_num: digit >>
+            register: alt!(
+                map_res!(digit, |d: CompleteStr| d.parse::<u8>()) => {
+                    |reg_num| Token::Register{reg_num}
+                } |
+                verify!(take_while1!(is_name_char), |name: CompleteStr| is_alias_name(&name)) => {
+                    named_register
+                }
+            ) >>
             (
-                Token::Register{
-                    reg_num: reg_num.parse::<u8>().unwrap()
+                register
+            )
+        )
+    )
+);
+
+named!(pub float_register<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            tag!("$f") >>
+            reg_num: map_res!(digit, |d: CompleteStr| d.parse::<u8>()) >>
+            (
+                Token::FloatRegister{
+                    reg_num
                 }
             )
         )
     )
 );
 
+// either kind of register, left for the encoder to check against the opcode
+named!(pub any_register<CompleteStr, Token>,
+    alt!(
+        float_register |
+        register
+    )
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -24,10 +94,68 @@ mod tests {
     #[test]
     fn test_parse_register() {
         let result = register(CompleteStr("$0"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = register(CompleteStr("0"));
-        assert_eq!(result.is_ok(), false);
-        let result = register(CompleteStr("$a"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
+        let result = register(CompleteStr("$256"));
+        assert!(result.is_err());
+        let result = register(CompleteStr("$_x"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_register_names() {
+        let result = register(CompleteStr("$sp"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::Register { reg_num: 29 }))
+        );
+        let result = any_register(CompleteStr("$fp"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::Register { reg_num: 30 }))
+        );
+        let result = register(CompleteStr("$t9 $a0"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr("$a0"), Token::Register { reg_num: 25 }))
+        );
+        let result = register(CompleteStr("$counter"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                Token::RegisterAlias {
+                    name: "counter".to_string()
+                }
+            ))
+        );
+
+        assert!(is_alias_name("counter"));
+        assert!(is_alias_name("fx"));
+        assert!(!is_alias_name("f3"));
+        assert!(!is_alias_name("3d"));
+    }
+
+    #[test]
+    fn test_parse_float_register() {
+        let result = float_register(CompleteStr("$f3"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::FloatRegister { reg_num: 3 }))
+        );
+        assert!(float_register(CompleteStr("$3")).is_err());
+        assert!(register(CompleteStr("$f3")).is_err());
+
+        let result = any_register(CompleteStr("$f1 $2"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr("$2"), Token::FloatRegister { reg_num: 1 }))
+        );
+        let result = any_register(CompleteStr("$2"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::Register { reg_num: 2 }))
+        );
     }
 }
@@ -0,0 +1,259 @@
+use crate::assembler::AssemblerError;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where a line of assembly came from. Input handed straight to the
+/// assembler, like a line typed at the REPL, has no file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+    // where the text starts once indentation is trimmed, counted from 1
+    pub column: usize,
+}
+
+impl Location {
+    /// Points `error` at this location, unless there is no file to point
+    /// into or the error already has a location.
+    pub fn locate(&self, error: AssemblerError) -> AssemblerError {
+        match (&self.file, error) {
+            (_, error @ AssemblerError::Located { .. }) | (None, error) => error,
+            (Some(file), error) => AssemblerError::Located {
+                file: file.clone(),
+                line: self.line,
+                error: Box::new(error),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub text: String,
+    pub location: Location,
+    // produced by a macro, so `location` is the invocation rather than
+    // where `text` is
+    pub expanded: bool,
+}
+
+/// Splits `raw` into trimmed lines, numbered from 1.
+pub fn source_lines(raw: &str, file: Option<&str>) -> Vec<SourceLine> {
+    raw.lines()
+        .enumerate()
+        .map(|(number, text)| SourceLine {
+            text: text.trim().to_string(),
+            location: Location {
+                file: file.map(str::to_string),
+                line: number + 1,
+                column: text.chars().take_while(|c| c.is_whitespace()).count() + 1,
+            },
+            expanded: false,
+        })
+        .collect()
+}
+
+// the quoted path of an `.include "path"` line
+fn include_path(line: &str) -> Option<&str> {
+    line[".include".len()..]
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')
+        .filter(|path| !path.is_empty() && !path.contains('"'))
+}
+
+/// Reads assembly, replacing every `.include "path"` line with the lines of
+/// that file. A path is looked up next to the file that includes it, or in
+/// the working directory for input without a file, then in `dirs` in order.
+pub struct Includes<'a> {
+    dirs: &'a [PathBuf],
+    enabled: bool,
+    // canonical paths of the files being read, innermost last
+    stack: Vec<PathBuf>,
+}
+
+impl<'a> Includes<'a> {
+    pub fn new(dirs: &'a [PathBuf], enabled: bool) -> Includes<'a> {
+        Includes {
+            dirs,
+            enabled,
+            stack: vec![],
+        }
+    }
+
+    pub fn read(&mut self, raw: &str) -> Result<Vec<SourceLine>, AssemblerError> {
+        let mut lines = vec![];
+        self.read_lines(raw, None, &mut lines)?;
+        Ok(lines)
+    }
+
+    pub fn read_file(&mut self, path: &Path) -> Result<Vec<SourceLine>, AssemblerError> {
+        let mut lines = vec![];
+        self.include(path, &mut lines)?;
+        Ok(lines)
+    }
+
+    fn read_lines(
+        &mut self,
+        raw: &str,
+        file: Option<&Path>,
+        lines: &mut Vec<SourceLine>,
+    ) -> Result<(), AssemblerError> {
+        let name = file.map(|file| file.display().to_string());
+        for line in source_lines(raw, name.as_deref()) {
+            if line.text.split_whitespace().next() != Some(".include") {
+                lines.push(line);
+                continue;
+            }
+            let result = match include_path(&line.text) {
+                _ if !self.enabled => Err(AssemblerError::IncludesDisabled),
+                Some(path) => match self.resolve(path, file) {
+                    Some(path) => self.include(&path, lines),
+                    None => Err(AssemblerError::IncludeNotFound {
+                        path: path.to_string(),
+                    }),
+                },
+                None => Err(AssemblerError::InvalidDirective {
+                    line: line.text.clone(),
+                }),
+            };
+            result.map_err(|e| line.location.locate(e))?;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, path: &str, from: Option<&Path>) -> Option<PathBuf> {
+        let base = from
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        std::iter::once(&base)
+            .chain(self.dirs)
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    fn include(&mut self, path: &Path, lines: &mut Vec<SourceLine>) -> Result<(), AssemblerError> {
+        let unreadable = |e: std::io::Error| AssemblerError::UnreadableFile {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        };
+        let canonical = path.canonicalize().map_err(unreadable)?;
+        if self.stack.contains(&canonical) {
+            return Err(AssemblerError::IncludeCycle {
+                path: path.display().to_string(),
+            });
+        }
+        let raw = fs::read_to_string(path).map_err(unreadable)?;
+
+        self.stack.push(canonical);
+        let result = self.read_lines(&raw, Some(path), lines);
+        self.stack.pop();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    // a fresh directory under the system temp dir holding `files`
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("alvm_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn texts(lines: &[SourceLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_read_includes() {
+        let dir = write_files(
+            "includes",
+            &[
+                ("main.asm", "load $0 #1\n.include \"lib/util.asm\"\nhlt"),
+                ("lib/util.asm", ".include \"more.asm\"\ninc $0"),
+                ("lib/more.asm", "dec $0"),
+                ("shared/common.asm", "neg $0"),
+            ],
+        );
+
+        let lines = Includes::new(&[], true)
+            .read_file(&dir.join("main.asm"))
+            .unwrap();
+        assert_eq!(texts(&lines), vec!["load $0 #1", "dec $0", "inc $0", "hlt"]);
+        assert_eq!(lines[1].location.line, 1);
+        assert!(lines[1]
+            .location
+            .file
+            .as_ref()
+            .unwrap()
+            .ends_with("more.asm"));
+        assert_eq!(lines[3].location.line, 3);
+        assert_eq!(source_lines("  hlt", None)[0].location.column, 3);
+
+        let dirs = [dir.join("shared")];
+        let lines = Includes::new(&dirs, true)
+            .read(".include \"common.asm\"")
+            .unwrap();
+        assert_eq!(texts(&lines), vec!["neg $0"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = write_files(
+            "include_errors",
+            &[
+                ("a.asm", "hlt\n.include \"b.asm\""),
+                ("b.asm", ".include \"a.asm\""),
+                ("missing.asm", "hlt\n\n.include \"nowhere.asm\""),
+            ],
+        );
+
+        let result = Includes::new(&[], true).read_file(&dir.join("a.asm"));
+        match result {
+            Err(AssemblerError::Located { file, line, error }) => {
+                assert!(file.ends_with("b.asm"));
+                assert_eq!(line, 1);
+                assert!(matches!(*error, AssemblerError::IncludeCycle { .. }));
+            }
+            result => panic!("expected an include cycle, got {:?}", result),
+        }
+
+        let result = Includes::new(&[], true).read_file(&dir.join("missing.asm"));
+        match result {
+            Err(AssemblerError::Located { line, error, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(
+                    *error,
+                    AssemblerError::IncludeNotFound {
+                        path: "nowhere.asm".to_string()
+                    }
+                );
+            }
+            result => panic!("expected a missing include, got {:?}", result),
+        }
+
+        assert_eq!(
+            Includes::new(&[], true).read(".include a.asm"),
+            Err(AssemblerError::InvalidDirective {
+                line: ".include a.asm".to_string()
+            })
+        );
+        assert_eq!(
+            Includes::new(&[], false).read(".include \"a.asm\""),
+            Err(AssemblerError::IncludesDisabled)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
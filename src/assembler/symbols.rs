@@ -0,0 +1,149 @@
+use crate::instruction::Operand;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    // byte offset of the labelled instruction in the VM program
+    pub offset: usize,
+}
+
+/// A name given to a value with `.equ` or `.define`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constant {
+    pub name: String,
+    pub value: i32,
+}
+
+/// A name given to a register with `.reg`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterAlias {
+    pub name: String,
+    // `Operand::Reg` or `Operand::FReg`
+    pub register: Operand,
+}
+
+/// How full each list in a `SymbolTable` was, see `SymbolTable::checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    symbols: usize,
+    constants: usize,
+    registers: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    constants: Vec<Constant>,
+    registers: Vec<RegisterAlias>,
+}
+
+impl SymbolTable {
+    pub fn add_symbol(&mut self, symbol: Symbol) {
+        self.symbols.push(symbol);
+    }
+
+    pub fn has_symbol(&self, name: &str) -> bool {
+        self.symbols.iter().any(|s| s.name == name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.offset)
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) {
+        self.constants.push(constant);
+    }
+
+    pub fn constant(&self, name: &str) -> Option<i32> {
+        self.constants
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.value)
+    }
+
+    pub fn constants(&self) -> &[Constant] {
+        &self.constants
+    }
+
+    pub fn add_register(&mut self, alias: RegisterAlias) {
+        self.registers.push(alias);
+    }
+
+    pub fn register(&self, name: &str) -> Option<&Operand> {
+        self.registers
+            .iter()
+            .find(|r| r.name == name)
+            .map(|r| &r.register)
+    }
+
+    pub fn registers(&self) -> &[RegisterAlias] {
+        &self.registers
+    }
+
+    /// Marks the current contents, so `restore` can drop everything added
+    /// since.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            symbols: self.symbols.len(),
+            constants: self.constants.len(),
+            registers: self.registers.len(),
+        }
+    }
+
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.symbols.truncate(checkpoint.symbols);
+        self.constants.truncate(checkpoint.constants);
+        self.registers.truncate(checkpoint.registers);
+    }
+
+    pub fn clear(&mut self) {
+        self.symbols.clear();
+        self.constants.clear();
+        self.registers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_table() {
+        let mut table = SymbolTable::default();
+        table.add_symbol(Symbol {
+            name: "loop".to_string(),
+            offset: 12,
+        });
+        assert!(table.has_symbol("loop"));
+        assert_eq!(table.value("loop"), Some(12));
+        assert_eq!(table.value("end"), None);
+
+        let checkpoint = table.checkpoint();
+        table.add_constant(Constant {
+            name: "SIZE".to_string(),
+            value: -4,
+        });
+        table.add_register(RegisterAlias {
+            name: "count".to_string(),
+            register: Operand::Reg(3),
+        });
+        assert_eq!(table.constant("SIZE"), Some(-4));
+        assert_eq!(table.register("count"), Some(&Operand::Reg(3)));
+        table.restore(checkpoint);
+        assert_eq!(table.constant("SIZE"), None);
+        assert_eq!(table.register("count"), None);
+        assert!(table.has_symbol("loop"));
+
+        table.clear();
+        assert!(!table.has_symbol("loop"));
+    }
+}
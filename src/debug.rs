@@ -0,0 +1,190 @@
+use crate::assembler::symbols::Symbol;
+use crate::vm::Fault;
+
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+// ends a program file that carries a debug section
+const DEBUG_MAGIC: &[u8; 8] = b"ALVMDBG\0";
+
+/// The source position of the instruction at `offset`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineEntry {
+    pub offset: usize,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for LineEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Where the instructions of a program came from, for those read from a
+/// file, and the names of its labels.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DebugInfo {
+    // sorted by offset
+    pub lines: Vec<LineEntry>,
+    pub labels: Vec<Symbol>,
+}
+
+impl DebugInfo {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.labels.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.labels.clear();
+    }
+
+    /// Adds `other`, whose offsets count from `base`.
+    pub fn append(&mut self, other: &DebugInfo, base: usize) {
+        self.lines.extend(other.lines.iter().map(|entry| LineEntry {
+            offset: base + entry.offset,
+            ..entry.clone()
+        }));
+        // `other` may go before what's here, if its code was placed lower
+        self.lines.sort_by_key(|entry| entry.offset);
+        self.labels.extend(other.labels.iter().map(|label| Symbol {
+            name: label.name.clone(),
+            offset: base + label.offset,
+        }));
+    }
+
+    pub fn line_at(&self, offset: usize) -> Option<&LineEntry> {
+        let index = self
+            .lines
+            .binary_search_by_key(&offset, |entry| entry.offset)
+            .ok()?;
+        Some(&self.lines[index])
+    }
+
+    /// The closest label at or before `offset`, and how far past it
+    /// `offset` is.
+    pub fn label_before(&self, offset: usize) -> Option<(&str, usize)> {
+        self.labels
+            .iter()
+            .filter(|label| label.offset <= offset)
+            .max_by_key(|label| label.offset)
+            .map(|label| (label.name.as_str(), offset - label.offset))
+    }
+
+    /// `offset` as a source position where one is known, followed by the
+    /// label it follows, like `loop.asm:12:5 (loop+4)`, or else `pc 8`.
+    pub fn position(&self, offset: usize) -> String {
+        let mut position = match self.line_at(offset) {
+            Some(entry) => entry.to_string(),
+            None => format!("pc {}", offset),
+        };
+        match self.label_before(offset) {
+            Some((name, 0)) => position.push_str(&format!(" ({})", name)),
+            Some((name, distance)) => position.push_str(&format!(" ({}+{})", name, distance)),
+            None => {}
+        }
+        position
+    }
+
+    /// Describes `fault` with its position in place of the raw pc.
+    pub fn describe(&self, fault: &Fault) -> String {
+        format!("{} at {}", fault.reason(), self.position(fault.pc()))
+    }
+}
+
+/// Appends `debug` to `code`, as the section in JSON, its length in 4 big
+/// endian bytes and a marker. The VM never runs past the code, since
+/// `split_debug_section` removes it again before loading.
+pub fn add_debug_section(code: &[u8], debug: &DebugInfo) -> Vec<u8> {
+    let json = serde_json::to_vec(debug).expect("debug info serializes");
+    let length = u32::try_from(json.len()).expect("debug info fits in 4GB");
+    let mut program = code.to_vec();
+    program.extend_from_slice(&json);
+    program.extend_from_slice(&length.to_be_bytes());
+    program.extend_from_slice(DEBUG_MAGIC);
+    program
+}
+
+/// Splits a program file into its code and debug info. A stripped program
+/// is all code.
+pub fn split_debug_section(program: &[u8]) -> (&[u8], Option<DebugInfo>) {
+    match debug_section(program) {
+        Some((code, debug)) => (code, Some(debug)),
+        None => (program, None),
+    }
+}
+
+fn debug_section(program: &[u8]) -> Option<(&[u8], DebugInfo)> {
+    let rest = program.strip_suffix(DEBUG_MAGIC)?;
+    let (rest, length) = rest.split_at(rest.len().checked_sub(4)?);
+    let length = u32::from_be_bytes(length.try_into().ok()?) as usize;
+    let (code, json) = rest.split_at(rest.len().checked_sub(length)?);
+    let debug = serde_json::from_slice(json).ok()?;
+    Some((code, debug))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debug_info() -> DebugInfo {
+        DebugInfo {
+            lines: vec![
+                LineEntry {
+                    offset: 0,
+                    file: "loop.asm".to_string(),
+                    line: 3,
+                    column: 5,
+                },
+                LineEntry {
+                    offset: 8,
+                    file: "loop.asm".to_string(),
+                    line: 5,
+                    column: 1,
+                },
+            ],
+            labels: vec![Symbol {
+                name: "loop".to_string(),
+                offset: 4,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_position() {
+        let debug = debug_info();
+        assert_eq!(debug.position(0), "loop.asm:3:5");
+        assert_eq!(debug.position(4), "pc 4 (loop)");
+        assert_eq!(debug.position(8), "loop.asm:5:1 (loop+4)");
+        assert_eq!(
+            debug.describe(&Fault::DivideByZero { pc: 8 }),
+            "division by zero at loop.asm:5:1 (loop+4)"
+        );
+
+        let mut linked = DebugInfo::default();
+        linked.append(&debug, 0);
+        linked.append(&debug, 12);
+        assert_eq!(linked.position(20), "loop.asm:5:1 (loop+4)");
+        assert_eq!(linked.label_before(12), Some(("loop", 8)));
+
+        let mut reversed = DebugInfo::default();
+        reversed.append(&debug, 12);
+        reversed.append(&debug, 0);
+        assert_eq!(reversed.position(8), "loop.asm:5:1 (loop+4)");
+        assert_eq!(reversed.position(12), "loop.asm:3:5 (loop+8)");
+    }
+
+    #[test]
+    fn test_debug_section() {
+        let code = vec![5, 0, 0, 0];
+        let program = add_debug_section(&code, &debug_info());
+        assert_eq!(
+            split_debug_section(&program),
+            (&code[..], Some(debug_info()))
+        );
+        assert_eq!(split_debug_section(&code), (&code[..], None));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::instruction::{DecodeError, Instruction, Opcode, INSTRUCTION_SIZE};
+
+#[derive(Debug, PartialEq)]
+pub struct DisassembledInstruction {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+pub fn disassemble(program: &[u8]) -> Vec<DisassembledInstruction> {
+    (0..program.len())
+        .step_by(INSTRUCTION_SIZE)
+        .map(|offset| disassemble_at(program, offset))
+        .collect()
+}
+
+/// Decodes the single instruction starting at `offset`, which must be
+/// inside `program`.
+pub fn disassemble_at(program: &[u8], offset: usize) -> DisassembledInstruction {
+    let end = (offset + INSTRUCTION_SIZE).min(program.len());
+    let bytes = &program[offset..end];
+    let text = match Instruction::decode(bytes) {
+        Ok((instruction, _)) => instruction.to_string(),
+        Err(DecodeError::Truncated) => format!("{} <truncated>", Opcode::from(bytes[0]).mnemonic()),
+        Err(DecodeError::IllegalOpcode { .. }) => Opcode::IGL.mnemonic().to_string(),
+    };
+
+    DisassembledInstruction {
+        offset,
+        bytes: bytes.to_vec(),
+        text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let program = vec![0, 0, 1, 244, 1, 0, 1, 2, 9, 0, 1, 0, 6, 3, 0, 0, 5, 0, 0, 0];
+        let texts: Vec<String> = disassemble(&program).into_iter().map(|i| i.text).collect();
+        assert_eq!(
+            texts,
+            vec!["load $0 #500", "add $0 $1 $2", "eq $0 $1", "jmp $3", "hlt"]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_truncated() {
+        let result = disassemble(&[0, 1]);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].text, "load <truncated>");
+        assert_eq!(result[0].bytes, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_disassemble_at() {
+        let program = vec![0, 0, 1, 244, 6, 3, 0, 0];
+        let result = disassemble_at(&program, 4);
+        assert_eq!(result.offset, 4);
+        assert_eq!(result.bytes, vec![6, 3, 0, 0]);
+        assert_eq!(result.text, "jmp $3");
+    }
+}
@@ -1,106 +1,452 @@
+use half::f16;
 use nom::types::CompleteStr;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Every instruction is encoded in exactly this many bytes: the opcode
+/// followed by its operands, zero padded. Registers take one byte and
+/// integers two, big endian. A LOADF64 of a float that doesn't fit in half
+/// precision is assembled to four such instructions, see
+/// `Instruction::encode`.
+pub const INSTRUCTION_SIZE: usize = 4;
+
+/// Number of integer registers, and of float registers.
+pub const REGISTER_COUNT: usize = 32;
+
+/// Kind of an operand following the opcode.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OperandKind {
+    // one byte register number, written `$n`
+    Register,
+    // one byte floating point register number, written `$fn`
+    FloatRegister,
+    // two byte integer, written `#n` or as a label address `@name`
+    Integer,
+    // one byte signed integer, written `#n`
+    SmallInteger,
+    // two byte half precision float, written `#x.y`, though LOADF64 takes
+    // any float, at the cost of four instructions (see `Instruction::encode`)
+    Float,
+    // two byte signed offset from the end of the instruction, written
+    // `#n`, or `@name` for the assembler to work out
+    Relative,
+}
+
+impl OperandKind {
+    pub fn size(self) -> usize {
+        match self {
+            OperandKind::Register | OperandKind::FloatRegister | OperandKind::SmallInteger => 1,
+            OperandKind::Integer | OperandKind::Float | OperandKind::Relative => 2,
+        }
+    }
+}
+
+/// Everything the VM, assembler, disassembler and REPL need to know about
+/// an opcode.
+#[derive(Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub opcode: Opcode,
+    pub byte: u8,
+    pub mnemonic: &'static str,
+    pub operands: &'static [OperandKind],
+    pub description: &'static str,
+}
+
+impl OpcodeInfo {
+    /// Bytes used by the opcode and its operands, before padding.
+    pub fn size(&self) -> usize {
+        1 + self.operands.iter().map(|o| o.size()).sum::<usize>()
+    }
+
+    /// Assembly syntax of the operands, e.g. `$a $b $c` for ADD and
+    /// `$fa #x.y` for LOADF64.
+    pub fn syntax(&self) -> String {
+        let mut names = ["a", "b", "c"].iter();
+        let operands: Vec<String> = self
+            .operands
+            .iter()
+            .map(|operand| match operand {
+                OperandKind::Register => format!("${}", names.next().unwrap_or(&"r")),
+                OperandKind::FloatRegister => format!("$f{}", names.next().unwrap_or(&"r")),
+                OperandKind::Integer => "#n".to_string(),
+                OperandKind::SmallInteger => "#i8".to_string(),
+                OperandKind::Float => "#x.y".to_string(),
+                OperandKind::Relative => "@label".to_string(),
+            })
+            .collect();
+        operands.join(" ")
+    }
+}
+
+// Declares `Opcode`, its byte and mnemonic conversions and the `OPCODES`
+// table from one list, so adding an opcode only takes one line.
+macro_rules! opcodes {
+    ($($name:ident = $byte:expr, $mnemonic:expr, [$($operand:ident),*], $description:expr;)*) => {
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub enum Opcode {
+            $($name,)*
+            IGL,
+        }
+
+        /// Every valid opcode, in declaration order.
+        pub const OPCODES: &[OpcodeInfo] = &[
+            $(OpcodeInfo {
+                opcode: Opcode::$name,
+                byte: $byte,
+                mnemonic: $mnemonic,
+                operands: &[$(OperandKind::$operand),*],
+                description: $description,
+            },)*
+        ];
+
+        impl From<u8> for Opcode {
+            fn from(v: u8) -> Self {
+                match v {
+                    $($byte => Opcode::$name,)*
+                    _ => Opcode::IGL,
+                }
+            }
+        }
+
+        impl From<Opcode> for u8 {
+            fn from(op: Opcode) -> Self {
+                match op {
+                    $(Opcode::$name => $byte,)*
+                    Opcode::IGL => ILLEGAL_OPCODE,
+                }
+            }
+        }
+
+        impl<'a> From<CompleteStr<'a>> for Opcode {
+            fn from(v: CompleteStr<'a>) -> Self {
+                match v {
+                    $(CompleteStr($mnemonic) => Opcode::$name,)*
+                    _ => Opcode::IGL,
+                }
+            }
+        }
+    };
+}
+
+/// Byte written for `Opcode::IGL`.
+pub const ILLEGAL_OPCODE: u8 = 200;
+
+opcodes! {
+    LOAD = 0, "load", [Register, Integer], "load an integer into $a";
+    ADD = 1, "add", [Register, Register, Register], "$c = $a + $b";
+    SUB = 2, "sub", [Register, Register, Register], "$c = $a - $b";
+    MUL = 3, "mul", [Register, Register, Register], "$c = $a * $b";
+    DIV = 4, "div", [Register, Register, Register], "$c = $a / $b, keeping the remainder";
+    HLT = 5, "hlt", [], "stop execution";
+    JMP = 6, "jmp", [Register], "jump to the address in $a";
+    JMPF = 7, "jmpf", [Register], "jump forward by signed $a bytes from the next instruction";
+    JMPB = 8, "jmpb", [Register], "jump backward by signed $a bytes from the next instruction";
+    EQ = 9, "eq", [Register, Register], "set the flag if $a == $b";
+    NEQ = 10, "neq", [Register, Register], "set the flag if $a != $b";
+    GTE = 11, "gte", [Register, Register], "set the flag if $a >= $b";
+    LTE = 12, "lte", [Register, Register], "set the flag if $a <= $b";
+    LT = 13, "lt", [Register, Register], "set the flag if $a < $b";
+    GT = 14, "gt", [Register, Register], "set the flag if $a > $b";
+    JMPE = 15, "jmpe", [Register], "jump to the address in $a if the flag is set";
+    LOADF64 = 16, "loadf64", [FloatRegister, Float], "load a float into $fa";
+    ADDF64 = 17, "addf64", [FloatRegister, FloatRegister, FloatRegister], "$fc = $fa + $fb";
+    SUBF64 = 18, "subf64", [FloatRegister, FloatRegister, FloatRegister], "$fc = $fa - $fb";
+    MULF64 = 19, "mulf64", [FloatRegister, FloatRegister, FloatRegister], "$fc = $fa * $fb";
+    DIVF64 = 20, "divf64", [FloatRegister, FloatRegister, FloatRegister], "$fc = $fa / $fb";
+    EQF64 = 21, "eqf64", [FloatRegister, FloatRegister], "set the flag if $fa == $fb";
+    NEQF64 = 22, "neqf64", [FloatRegister, FloatRegister], "set the flag if $fa != $fb";
+    GTF64 = 23, "gtf64", [FloatRegister, FloatRegister], "set the flag if $fa > $fb";
+    GTEF64 = 24, "gtef64", [FloatRegister, FloatRegister], "set the flag if $fa >= $fb";
+    LTF64 = 25, "ltf64", [FloatRegister, FloatRegister], "set the flag if $fa < $fb";
+    LTEF64 = 26, "ltef64", [FloatRegister, FloatRegister], "set the flag if $fa <= $fb";
+    ITOF = 27, "itof", [Register, FloatRegister], "$fb = $a as a float";
+    FTOI = 28, "ftoi", [FloatRegister, Register], "$b = $fa rounded toward zero, saturating";
+    DIVU = 29, "divu", [Register, Register, Register], "$c = $a / $b unsigned, keeping the remainder";
+    REMU = 30, "remu", [Register, Register, Register], "$c = $a % $b unsigned";
+    MFREM = 31, "mfrem", [Register], "$a = the remainder of the last div, divu or divi";
+    GTU = 32, "gtu", [Register, Register], "set the flag if $a > $b unsigned";
+    GTEU = 33, "gteu", [Register, Register], "set the flag if $a >= $b unsigned";
+    LTU = 34, "ltu", [Register, Register], "set the flag if $a < $b unsigned";
+    LTEU = 35, "lteu", [Register, Register], "set the flag if $a <= $b unsigned";
+    JMPR = 36, "jmpr", [Relative], "jump to a label, or by #n bytes from the next instruction";
+    JMPRE = 37, "jmpre", [Relative], "jmpr if the flag is set";
+    ADDI = 38, "addi", [Register, Register, SmallInteger], "$b = $a + n, n from -128 to 127";
+    SUBI = 39, "subi", [Register, Register, SmallInteger], "$b = $a - n, n from -128 to 127";
+    MULI = 40, "muli", [Register, Register, SmallInteger], "$b = $a * n, n from -128 to 127";
+    DIVI = 41, "divi", [Register, Register, SmallInteger], "$b = $a / n, keeping the remainder";
+    JMPI = 42, "jmpi", [Integer], "jump to a label or address";
+    JMPEI = 43, "jmpei", [Integer], "jmpi if the flag is set";
+    MOV = 44, "mov", [Register, Register], "$a = $b";
+    INC = 45, "inc", [Register], "$a = $a + 1";
+    DEC = 46, "dec", [Register], "$a = $a - 1";
+    NEG = 47, "neg", [Register], "$a = -$a";
+    LOADF64W = 48, "loadf64w", [FloatRegister, Integer], "shift the bits of $fa up 16, then or in n";
+}
+
+impl Opcode {
+    /// Table entry of this opcode, `None` for `IGL`.
+    pub fn info(self) -> Option<&'static OpcodeInfo> {
+        // OPCODES lists the variants in order, and IGL comes last
+        OPCODES.get(self as usize)
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        self.info().map_or("igl", |info| info.mnemonic)
+    }
+
+    pub fn operands(self) -> &'static [OperandKind] {
+        self.info().map_or(&[], |info| info.operands)
+    }
+}
+
+/// Operand of a decoded instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Reg(u8),
+    FReg(u8),
+    Imm16(u16),
+    // an integer still to be narrowed to 16 bits, as written in assembly
+    Imm32(i32),
+    // a label address still to be resolved
+    Label(String),
+    // encoded as a half precision float, or as LOADF64W for LOADF64
+    Float(f64),
+    // a relative jump distance
+    Offset(i16),
+    Imm8(i8),
+}
+
+impl Operand {
+    // whether this operand can be encoded as `kind`; integers written in
+    // assembly also serve as floats
+    fn fits(&self, kind: OperandKind) -> bool {
+        match self {
+            Operand::Reg(_) => kind == OperandKind::Register,
+            Operand::FReg(_) => kind == OperandKind::FloatRegister,
+            Operand::Imm16(_) => kind == OperandKind::Integer,
+            Operand::Label(_) => kind == OperandKind::Integer || kind == OperandKind::Relative,
+            Operand::Imm32(_) => {
+                kind != OperandKind::Register && kind != OperandKind::FloatRegister
+            }
+            Operand::Float(_) => kind == OperandKind::Float,
+            Operand::Offset(_) => kind == OperandKind::Relative,
+            Operand::Imm8(_) => kind == OperandKind::SmallInteger,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(register) => write!(f, "${}", register),
+            Operand::FReg(register) => write!(f, "$f{}", register),
+            Operand::Imm16(value) => write!(f, "#{}", value),
+            Operand::Imm32(value) => write!(f, "#{}", value),
+            Operand::Label(name) => write!(f, "@{}", name),
+            // always with a fraction, so it reads back as a float
+            Operand::Float(value) => write!(f, "#{:?}", value),
+            Operand::Offset(value) => write!(f, "#{}", value),
+            Operand::Imm8(value) => write!(f, "#{}", value),
+        }
+    }
+}
 
 #[derive(Copy, Clone, Debug, PartialEq)]
-pub enum Opcode {
-    LOAD,
-    ADD,
-    SUB,
-    MUL,
-    DIV,
-    HLT,
-    JMP,
-    JMPF,
-    JMPB,
-    EQ,
-    NEQ,
-    GTE,
-    LTE,
-    LT,
-    GT,
-    JMPE,
-    IGL,
-}
-
-impl From<u8> for Opcode {
-    fn from(v: u8) -> Self {
-        match v {
-            0 => Opcode::LOAD,
-            1 => Opcode::ADD,
-            2 => Opcode::SUB,
-            3 => Opcode::MUL,
-            4 => Opcode::DIV,
-            5 => Opcode::HLT,
-            6 => Opcode::JMP,
-            7 => Opcode::JMPF,
-            8 => Opcode::JMPB,
-            9 => Opcode::EQ,
-            10 => Opcode::NEQ,
-            11 => Opcode::GTE,
-            12 => Opcode::LTE,
-            13 => Opcode::LT,
-            14 => Opcode::GT,
-            15 => Opcode::JMPE,
-            _ => Opcode::IGL,
-        }
-    }
-}
-
-impl From<Opcode> for u8 {
-    fn from(op: Opcode) -> Self {
-        match op {
-            Opcode::LOAD => 0,
-            Opcode::ADD => 1,
-            Opcode::SUB => 2,
-            Opcode::MUL => 3,
-            Opcode::DIV => 4,
-            Opcode::HLT => 5,
-            Opcode::JMP => 6,
-            Opcode::JMPF => 7,
-            Opcode::JMPB => 8,
-            Opcode::EQ => 9,
-            Opcode::NEQ => 10,
-            Opcode::GTE => 11,
-            Opcode::LTE => 12,
-            Opcode::LT => 13,
-            Opcode::GT => 14,
-            Opcode::JMPE => 15,
-            Opcode::IGL => 200,
-        }
-    }
-}
-
-impl<'a> From<CompleteStr<'a>> for Opcode {
-    fn from(v: CompleteStr<'a>) -> Self {
-        match v {
-            CompleteStr("load") => Opcode::LOAD,
-            CompleteStr("add") => Opcode::ADD,
-            CompleteStr("sub") => Opcode::SUB,
-            CompleteStr("mul") => Opcode::MUL,
-            CompleteStr("div") => Opcode::DIV,
-            CompleteStr("hlt") => Opcode::HLT,
-            CompleteStr("jmp") => Opcode::JMP,
-            CompleteStr("jmpf") => Opcode::JMPF,
-            CompleteStr("jmpb") => Opcode::JMPB,
-            CompleteStr("eq") => Opcode::EQ,
-            CompleteStr("neq") => Opcode::NEQ,
-            CompleteStr("gte") => Opcode::GTE,
-            CompleteStr("lte") => Opcode::LTE,
-            CompleteStr("lt") => Opcode::LT,
-            CompleteStr("gt") => Opcode::GT,
-            CompleteStr("jmpe") => Opcode::JMPE,
-            _ => Opcode::IGL,
+pub enum DecodeError {
+    // fewer than `INSTRUCTION_SIZE` bytes were left
+    Truncated,
+    IllegalOpcode { opcode: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "truncated instruction"),
+            DecodeError::IllegalOpcode { opcode } => write!(f, "illegal opcode {}", opcode),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
+pub enum EncodeError {
+    IllegalOpcode,
+    InvalidOperands {
+        mnemonic: &'static str,
+        syntax: String,
+    },
+    ImmediateOutOfRange {
+        value: i32,
+    },
+    InexactFloat {
+        value: f64,
+    },
+    UnresolvedLabel {
+        name: String,
+    },
+    InvalidRegister {
+        register: u8,
+    },
+}
+
+/// An opcode with its operands, in the shape given by `OPCODES`.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Instruction {
-    opcode: Opcode,
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>,
 }
 
 impl Instruction {
-    pub fn new(opcode: Opcode) -> Instruction {
-        Instruction { opcode }
+    pub fn new(opcode: Opcode, operands: Vec<Operand>) -> Instruction {
+        Instruction { opcode, operands }
+    }
+
+    /// Decodes the instruction at the start of `bytes`, returning it with
+    /// the number of bytes it takes.
+    pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize), DecodeError> {
+        if bytes.len() < INSTRUCTION_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        let opcode = Opcode::from(bytes[0]);
+        let info = opcode
+            .info()
+            .ok_or(DecodeError::IllegalOpcode { opcode: bytes[0] })?;
+
+        let mut operands = Vec::with_capacity(info.operands.len());
+        let mut offset = 1;
+        for kind in info.operands {
+            let imm16 = || (u16::from(bytes[offset]) << 8) | u16::from(bytes[offset + 1]);
+            operands.push(match kind {
+                OperandKind::Register => Operand::Reg(bytes[offset]),
+                OperandKind::FloatRegister => Operand::FReg(bytes[offset]),
+                OperandKind::Integer => Operand::Imm16(imm16()),
+                OperandKind::Float => Operand::Float(f16::from_bits(imm16()).to_f64()),
+                OperandKind::Relative => Operand::Offset(imm16() as i16),
+                OperandKind::SmallInteger => Operand::Imm8(bytes[offset] as i8),
+            });
+            offset += kind.size();
+        }
+        Ok((Instruction { opcode, operands }, INSTRUCTION_SIZE))
+    }
+
+    /// Number of bytes `encode` produces, `INSTRUCTION_SIZE` for one
+    /// instruction or four times that for a wide LOADF64.
+    pub fn size(&self) -> usize {
+        match self.wide_float() {
+            Some(_) => 4 * INSTRUCTION_SIZE,
+            None => INSTRUCTION_SIZE,
+        }
+    }
+
+    /// Encodes the instruction into `INSTRUCTION_SIZE` bytes. A LOADF64 of
+    /// a float half precision can't hold exactly becomes four LOADF64W
+    /// instructions of `INSTRUCTION_SIZE` bytes each, every one shifting in
+    /// 16 bits of the float from the top. Labels must already be resolved
+    /// to addresses.
+    pub fn encode(&self) -> Result<Vec<u8>, EncodeError> {
+        if let Some((register, value)) = self.wide_float() {
+            let mut bytes = vec![];
+            for word in value.to_bits().to_be_bytes().chunks(2) {
+                let word = u16::from_be_bytes([word[0], word[1]]);
+                let operands = vec![Operand::FReg(register), Operand::Imm16(word)];
+                bytes.append(&mut Instruction::new(Opcode::LOADF64W, operands).encode()?);
+            }
+            return Ok(bytes);
+        }
+
+        let info = self.opcode.info().ok_or(EncodeError::IllegalOpcode)?;
+        let matches = self.operands.len() == info.operands.len()
+            && self
+                .operands
+                .iter()
+                .zip(info.operands)
+                .all(|(operand, kind)| operand.fits(*kind));
+        if !matches {
+            return Err(EncodeError::InvalidOperands {
+                mnemonic: info.mnemonic,
+                syntax: info.syntax(),
+            });
+        }
+
+        let mut bytes = vec![info.byte];
+        for (operand, kind) in self.operands.iter().zip(info.operands) {
+            match operand {
+                Operand::Reg(register) | Operand::FReg(register)
+                    if usize::from(*register) >= REGISTER_COUNT =>
+                {
+                    return Err(EncodeError::InvalidRegister {
+                        register: *register,
+                    });
+                }
+                Operand::Reg(register) | Operand::FReg(register) => bytes.push(*register),
+                Operand::Imm16(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+                Operand::Imm32(value) if *kind == OperandKind::Float => {
+                    bytes.extend_from_slice(&encode_float(f64::from(*value))?.to_be_bytes());
+                }
+                Operand::Imm32(value) if *kind == OperandKind::SmallInteger => {
+                    match i8::try_from(*value) {
+                        Ok(value) => bytes.push(value as u8),
+                        Err(_) => return Err(EncodeError::ImmediateOutOfRange { value: *value }),
+                    }
+                }
+                Operand::Imm32(value) => {
+                    let fits = if *kind == OperandKind::Relative {
+                        i16::try_from(*value).is_ok()
+                    } else {
+                        u16::try_from(*value).is_ok()
+                    };
+                    if !fits {
+                        return Err(EncodeError::ImmediateOutOfRange { value: *value });
+                    }
+                    bytes.extend_from_slice(&(*value as u16).to_be_bytes());
+                }
+                Operand::Imm8(value) => bytes.push(*value as u8),
+                Operand::Offset(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+                Operand::Float(value) => {
+                    bytes.extend_from_slice(&encode_float(*value)?.to_be_bytes());
+                }
+                Operand::Label(name) => {
+                    return Err(EncodeError::UnresolvedLabel { name: name.clone() });
+                }
+            }
+        }
+        bytes.resize(INSTRUCTION_SIZE, 0);
+        Ok(bytes)
+    }
+
+    // the register and value of a LOADF64 whose float needs all 64 bits
+    fn wide_float(&self) -> Option<(u8, f64)> {
+        let (register, value) = match (self.opcode, self.operands.as_slice()) {
+            (Opcode::LOADF64, [Operand::FReg(register), Operand::Float(value)]) => {
+                (*register, *value)
+            }
+            (Opcode::LOADF64, [Operand::FReg(register), Operand::Imm32(value)]) => {
+                (*register, f64::from(*value))
+            }
+            _ => return None,
+        };
+        match encode_float(value) {
+            Ok(_) => None,
+            Err(_) => Some((register, value)),
+        }
+    }
+}
+
+// half precision bits of `value`, which must be exactly representable
+fn encode_float(value: f64) -> Result<u16, EncodeError> {
+    let half = f16::from_f64(value);
+    if half.to_f64() != value {
+        return Err(EncodeError::InexactFloat { value });
+    }
+    Ok(half.to_bits())
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+        for operand in &self.operands {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
     }
 }
 
@@ -117,10 +463,118 @@ mod tests {
 
     #[test]
     fn test_create_instruction() {
-        let instruction = Instruction::new(Opcode::HLT);
+        let instruction = Instruction::new(Opcode::HLT, vec![]);
         assert_eq!(instruction.opcode, Opcode::HLT);
     }
 
+    #[test]
+    fn test_decode() {
+        let (load, size) = Instruction::decode(&[0, 1, 1, 244, 9]).unwrap();
+        assert_eq!(size, INSTRUCTION_SIZE);
+        assert_eq!(
+            load,
+            Instruction::new(Opcode::LOAD, vec![Operand::Reg(1), Operand::Imm16(500)])
+        );
+        assert_eq!(load.to_string(), "load $1 #500");
+
+        assert_eq!(Instruction::decode(&[5, 0, 0]), Err(DecodeError::Truncated));
+        assert_eq!(
+            Instruction::decode(&[200, 0, 0, 0]),
+            Err(DecodeError::IllegalOpcode { opcode: 200 })
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        for info in OPCODES {
+            let bytes = [info.byte, 1, 2, 3];
+            let (instruction, _) = Instruction::decode(&bytes).unwrap();
+            let encoded = instruction.encode().unwrap();
+            assert_eq!(&encoded[..info.size()], &bytes[..info.size()]);
+        }
+
+        let load = Instruction::new(Opcode::LOAD, vec![Operand::Reg(0), Operand::Imm32(65535)]);
+        assert_eq!(load.encode(), Ok(vec![0, 0, 255, 255]));
+        let load = Instruction::new(Opcode::LOAD, vec![Operand::Reg(0), Operand::Imm32(-1)]);
+        assert_eq!(
+            load.encode(),
+            Err(EncodeError::ImmediateOutOfRange { value: -1 })
+        );
+        let jmpr = Instruction::new(Opcode::JMPR, vec![Operand::Imm32(-8)]);
+        assert_eq!(jmpr.encode(), Ok(vec![36, 255, 248, 0]));
+        assert_eq!(
+            Instruction::decode(&[36, 255, 248, 0]).unwrap().0,
+            Instruction::new(Opcode::JMPR, vec![Operand::Offset(-8)])
+        );
+        let jmpr = Instruction::new(Opcode::JMPR, vec![Operand::Imm32(40000)]);
+        assert!(jmpr.encode().is_err());
+
+        let addi = Instruction::new(
+            Opcode::ADDI,
+            vec![Operand::Reg(0), Operand::Reg(1), Operand::Imm32(-2)],
+        );
+        assert_eq!(addi.encode(), Ok(vec![38, 0, 1, 254]));
+        assert_eq!(
+            Instruction::decode(&[38, 0, 1, 254]).unwrap().0.to_string(),
+            "addi $0 $1 #-2"
+        );
+        let addi = Instruction::new(
+            Opcode::ADDI,
+            vec![Operand::Reg(0), Operand::Reg(1), Operand::Imm32(128)],
+        );
+        assert_eq!(
+            addi.encode(),
+            Err(EncodeError::ImmediateOutOfRange { value: 128 })
+        );
+        let load = Instruction::new(Opcode::LOAD, vec![Operand::Reg(0), Operand::Imm32(70000)]);
+        assert_eq!(
+            load.encode(),
+            Err(EncodeError::ImmediateOutOfRange { value: 70000 })
+        );
+        let jmp = Instruction::new(Opcode::JMP, vec![Operand::Label("end".to_string())]);
+        assert!(jmp.encode().is_err());
+        let hlt = Instruction::new(Opcode::HLT, vec![Operand::Reg(0)]);
+        assert!(hlt.encode().is_err());
+        let inc = Instruction::new(Opcode::INC, vec![Operand::Reg(32)]);
+        assert_eq!(
+            inc.encode(),
+            Err(EncodeError::InvalidRegister { register: 32 })
+        );
+    }
+
+    #[test]
+    fn test_float_operands() {
+        let load = Instruction::new(Opcode::LOADF64, vec![Operand::FReg(2), Operand::Float(1.5)]);
+        let bytes = load.encode().unwrap();
+        assert_eq!(bytes, vec![16, 2, 0x3e, 0x00]);
+        assert_eq!(Instruction::decode(&bytes).unwrap().0, load);
+        assert_eq!(load.to_string(), "loadf64 $f2 #1.5");
+
+        let load = Instruction::new(Opcode::LOADF64, vec![Operand::FReg(0), Operand::Imm32(3)]);
+        assert_eq!(load.encode(), Ok(vec![16, 0, 0x42, 0x00]));
+        assert_eq!(load.size(), INSTRUCTION_SIZE);
+
+        let load = Instruction::new(Opcode::LOADF64, vec![Operand::FReg(1), Operand::Float(0.1)]);
+        assert_eq!(load.size(), 4 * INSTRUCTION_SIZE);
+        assert_eq!(
+            load.encode(),
+            Ok(vec![
+                48, 1, 0x3f, 0xb9, 48, 1, 0x99, 0x99, 48, 1, 0x99, 0x99, 48, 1, 0x99, 0x9a
+            ])
+        );
+        let load = Instruction::new(
+            Opcode::LOADF64,
+            vec![Operand::FReg(0), Operand::Imm32(70001)],
+        );
+        assert_eq!(load.size(), 4 * INSTRUCTION_SIZE);
+
+        let add = Instruction::new(
+            Opcode::ADDF64,
+            vec![Operand::FReg(0), Operand::Reg(1), Operand::FReg(2)],
+        );
+        assert!(add.encode().is_err());
+    }
+
     #[test]
     fn test_str_to_opcode() {
         let opcode = Opcode::from(CompleteStr("load"));
@@ -129,4 +583,25 @@ mod tests {
         let opcode = Opcode::from(CompleteStr("illegal"));
         assert_eq!(opcode, Opcode::IGL);
     }
+
+    #[test]
+    fn test_opcode_table() {
+        for (index, info) in OPCODES.iter().enumerate() {
+            assert_eq!(info.opcode as usize, index);
+            assert_eq!(info.opcode.info(), Some(info));
+            assert_eq!(Opcode::from(info.byte), info.opcode);
+            assert_eq!(u8::from(info.opcode), info.byte);
+            assert_eq!(Opcode::from(CompleteStr(info.mnemonic)), info.opcode);
+            assert!(info.size() <= INSTRUCTION_SIZE);
+        }
+        assert_eq!(Opcode::from(ILLEGAL_OPCODE), Opcode::IGL);
+        assert_eq!(Opcode::IGL.info(), None);
+    }
+
+    #[test]
+    fn test_opcode_syntax() {
+        assert_eq!(Opcode::ADD.info().unwrap().syntax(), "$a $b $c");
+        assert_eq!(Opcode::LOAD.info().unwrap().syntax(), "$a #n");
+        assert_eq!(Opcode::HLT.info().unwrap().syntax(), "");
+    }
 }
@@ -0,0 +1,8 @@
+#[macro_use]
+pub mod vm;
+pub mod assembler;
+pub mod debug;
+pub mod disassembler;
+pub mod instruction;
+pub mod linker;
+pub mod repl;
@@ -0,0 +1,308 @@
+use crate::assembler::symbols::Symbol;
+use crate::debug::DebugInfo;
+use crate::instruction::INSTRUCTION_SIZE;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelocationKind {
+    // the field holds the address itself
+    Absolute,
+    // the field holds the distance from the end of its instruction
+    Relative,
+}
+
+/// A two byte operand field the linker fills in with `target + addend`,
+/// where the target is `symbol`, or the start of the object itself when
+/// there is no symbol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relocation {
+    // offset of the field in the object's code
+    pub offset: usize,
+    pub kind: RelocationKind,
+    pub symbol: Option<String>,
+    pub addend: i32,
+}
+
+/// A module assembled on its own, with labels counted from the start of its
+/// code, ready to be combined with others by `link`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    pub code: Vec<u8>,
+    // labels other objects may use
+    pub exports: Vec<Symbol>,
+    // symbols this object expects another one to export
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+    // missing from objects assembled without it
+    #[serde(default)]
+    pub debug: DebugInfo,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    DuplicateSymbol { name: String },
+    UndefinedSymbol { name: String },
+    InvalidRelocation { offset: usize },
+    AddressOutOfRange { value: i64 },
+    PartialInstruction { length: usize },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol { name } => write!(f, "Symbol {} exported twice", name),
+            LinkError::UndefinedSymbol { name } => write!(f, "Symbol {} is not exported", name),
+            LinkError::InvalidRelocation { offset } => {
+                write!(f, "Relocation at {} is outside the code", offset)
+            }
+            LinkError::AddressOutOfRange { value } => {
+                write!(f, "Linked value {} doesn't fit in its field", value)
+            }
+            LinkError::PartialInstruction { length } => {
+                write!(
+                    f,
+                    "Object of {} bytes ends partway through an instruction",
+                    length
+                )
+            }
+        }
+    }
+}
+
+// where each object starts once they're placed one after the other
+fn bases(objects: &[Object]) -> Vec<usize> {
+    objects
+        .iter()
+        .scan(0, |end, object| {
+            let base = *end;
+            *end += object.code.len();
+            Some(base)
+        })
+        .collect()
+}
+
+/// Places `objects` one after the other and fills in every relocation.
+/// Execution starts at the beginning of the first object.
+pub fn link(objects: &[Object]) -> Result<Vec<u8>, LinkError> {
+    // later objects have to start on an instruction
+    if let Some(object) = objects
+        .iter()
+        .find(|o| o.code.len() % INSTRUCTION_SIZE != 0)
+    {
+        return Err(LinkError::PartialInstruction {
+            length: object.code.len(),
+        });
+    }
+    let bases = bases(objects);
+    let mut program: Vec<u8> = objects
+        .iter()
+        .flat_map(|o| o.code.iter().copied())
+        .collect();
+
+    let mut exports = HashMap::new();
+    for (object, base) in objects.iter().zip(&bases) {
+        for symbol in &object.exports {
+            if exports.insert(&symbol.name, base + symbol.offset).is_some() {
+                return Err(LinkError::DuplicateSymbol {
+                    name: symbol.name.clone(),
+                });
+            }
+        }
+    }
+    let address = |name: &String| {
+        exports
+            .get(name)
+            .copied()
+            .ok_or_else(|| LinkError::UndefinedSymbol { name: name.clone() })
+    };
+
+    for (object, base) in objects.iter().zip(&bases) {
+        for name in &object.imports {
+            address(name)?;
+        }
+        for relocation in &object.relocations {
+            let end = relocation.offset.checked_add(2);
+            if end.is_none_or(|end| end > object.code.len()) {
+                return Err(LinkError::InvalidRelocation {
+                    offset: relocation.offset,
+                });
+            }
+            let target = match &relocation.symbol {
+                Some(name) => address(name)?,
+                None => *base,
+            };
+            let field = base + relocation.offset;
+            let mut value = target as i64 + i64::from(relocation.addend);
+            let bytes = match relocation.kind {
+                RelocationKind::Absolute => u16::try_from(value).map(u16::to_be_bytes),
+                RelocationKind::Relative => {
+                    let end = field - field % INSTRUCTION_SIZE + INSTRUCTION_SIZE;
+                    value -= end as i64;
+                    i16::try_from(value).map(i16::to_be_bytes)
+                }
+            };
+            let bytes = bytes.map_err(|_| LinkError::AddressOutOfRange { value })?;
+            program[field..field + 2].copy_from_slice(&bytes);
+        }
+    }
+    Ok(program)
+}
+
+/// The debug info of the program `link` makes from `objects`.
+pub fn link_debug_info(objects: &[Object]) -> DebugInfo {
+    let mut debug = DebugInfo::default();
+    for (object, base) in objects.iter().zip(bases(objects)) {
+        debug.append(&object.debug, base);
+    }
+    debug
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(name: &str, offset: usize) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_link() {
+        // load $0 @data; jmpr @far
+        let main = Object {
+            code: vec![0, 0, 0, 0, 36, 0, 0, 0],
+            exports: vec![],
+            imports: vec!["data".to_string(), "far".to_string()],
+            relocations: vec![
+                Relocation {
+                    offset: 2,
+                    kind: RelocationKind::Absolute,
+                    symbol: Some("data".to_string()),
+                    addend: 4,
+                },
+                Relocation {
+                    offset: 5,
+                    kind: RelocationKind::Relative,
+                    symbol: Some("far".to_string()),
+                    addend: 0,
+                },
+            ],
+            ..Object::default()
+        };
+        // far: load $1 @here; here: hlt
+        let library = Object {
+            code: vec![0, 1, 0, 4, 5, 0, 0, 0],
+            exports: vec![export("far", 0), export("data", 4)],
+            imports: vec![],
+            relocations: vec![Relocation {
+                offset: 2,
+                kind: RelocationKind::Absolute,
+                symbol: None,
+                addend: 4,
+            }],
+            ..Object::default()
+        };
+
+        let program = link(&[main, library]).unwrap();
+        assert_eq!(
+            program,
+            vec![0, 0, 0, 16, 36, 0, 0, 0, 0, 1, 0, 12, 5, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let library = Object {
+            code: vec![5, 0, 0, 0],
+            exports: vec![export("start", 0)],
+            ..Object::default()
+        };
+        assert_eq!(
+            link(&[library.clone(), library.clone()]),
+            Err(LinkError::DuplicateSymbol {
+                name: "start".to_string()
+            })
+        );
+
+        let main = Object {
+            code: vec![42, 0, 0, 0],
+            imports: vec!["missing".to_string()],
+            ..Object::default()
+        };
+        assert_eq!(
+            link(&[main]),
+            Err(LinkError::UndefinedSymbol {
+                name: "missing".to_string()
+            })
+        );
+
+        let main = Object {
+            code: vec![42, 0, 0, 0],
+            relocations: vec![Relocation {
+                offset: 1,
+                kind: RelocationKind::Absolute,
+                symbol: None,
+                addend: -8,
+            }],
+            ..Object::default()
+        };
+        assert_eq!(
+            link(&[library.clone(), main.clone()]),
+            Err(LinkError::AddressOutOfRange { value: -4 })
+        );
+
+        let mut far = main.clone();
+        far.relocations[0].offset = usize::MAX;
+        assert_eq!(
+            link(&[far]),
+            Err(LinkError::InvalidRelocation { offset: usize::MAX })
+        );
+
+        let truncated = Object {
+            code: vec![5, 0],
+            ..Object::default()
+        };
+        assert_eq!(
+            link(&[truncated, main]),
+            Err(LinkError::PartialInstruction { length: 2 })
+        );
+    }
+
+    #[test]
+    fn test_link_assembled() {
+        use crate::assembler::Assembler;
+        use crate::vm::VM;
+
+        let main = Assembler::default()
+            .assemble_object(
+                ".import double\n\
+                 load $0 #21\n\
+                 load $1 @back\n\
+                 jmpr @double\n\
+                 back: hlt\n",
+            )
+            .unwrap();
+        let library = Assembler::default()
+            .assemble_object(".export double\ndouble: add $0 $0 $0\njmp $1\n")
+            .unwrap();
+
+        let objects = [main, library];
+        let debug = link_debug_info(&objects);
+        assert_eq!(debug.position(16), "pc 16 (double)");
+        assert_eq!(debug.position(12), "pc 12 (back)");
+
+        let mut vm = VM::default();
+        vm.add_bytes(&link(&objects).unwrap());
+        vm.run();
+        assert_eq!(vm.fault(), None);
+        assert!(vm.halted());
+        assert_eq!(vm.registers[0], 42);
+    }
+}
@@ -1,11 +1,237 @@
-#[macro_use]
+use alvm::assembler::Assembler;
+use alvm::debug;
+use alvm::linker::{self, Object};
+use alvm::repl;
+use alvm::repl::server::Server;
+use alvm::vm::VM;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, IsTerminal};
+use std::path::PathBuf;
+use std::process;
 
-pub mod vm;
-pub mod assembler;
-pub mod instruction;
-pub mod repl;
+const DEFAULT_MAX_CONNECTIONS: usize = 4;
+
+// `alvm --listen <addr> [--max-connections <n>]` serves remote sessions,
+// authenticated with the token in $ALVM_TOKEN
+fn listen(args: &[String]) -> bool {
+    let mut addr = None;
+    let mut max_connections = DEFAULT_MAX_CONNECTIONS;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--listen", Some(value)) => addr = Some(value),
+            ("--max-connections", Some(value)) => match value.parse() {
+                Ok(value) => max_connections = value,
+                Err(_) => {
+                    eprintln!("Invalid connection limit: {}", value);
+                    return false;
+                }
+            },
+            _ => {
+                eprintln!("Usage: alvm --listen <addr> [--max-connections <n>]");
+                return false;
+            }
+        }
+    }
+
+    let token = match env::var("ALVM_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => {
+            eprintln!("Set ALVM_TOKEN to the token remote clients must send");
+            return false;
+        }
+    };
+    let addr = addr.expect("--listen is the first argument");
+    match Server::bind(addr.as_str(), &token, max_connections) {
+        Ok(server) => {
+            println!("listening on {}", addr);
+            server.run();
+            true
+        }
+        Err(e) => {
+            eprintln!("Unable to listen on {}: {}", addr, e);
+            false
+        }
+    }
+}
+
+// `alvm assemble [-I <dir>]... <source> [-o <object>]` writes the object
+// as JSON, next to the source unless `-o` says otherwise
+fn assemble(args: &[String], include_dirs: Vec<PathBuf>) -> bool {
+    let mut assembler = Assembler {
+        include_dirs,
+        ..Assembler::default()
+    };
+    let usage = || {
+        eprintln!("Usage: alvm assemble [-I <dir>]... <source> [-o <object>]");
+        false
+    };
+    let mut sources = vec![];
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-I" => match args.next() {
+                Some(dir) => assembler.include_dirs.push(PathBuf::from(dir)),
+                None => return usage(),
+            },
+            "-o" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return usage(),
+            },
+            path => sources.push(path),
+        }
+    }
+    let source = match sources.as_slice() {
+        [source] => PathBuf::from(source),
+        _ => return usage(),
+    };
+    let output = output.unwrap_or_else(|| source.with_extension("o"));
+
+    let object = match assembler.assemble_object_file(&source) {
+        Ok(object) => object,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    let json = serde_json::to_string(&object).expect("objects serialize");
+    match fs::write(&output, json) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Unable to write {}: {}", output.display(), e);
+            false
+        }
+    }
+}
+
+// `alvm link <object>... -o <program> [--strip]` writes the program bytes,
+// followed by a debug section unless `--strip` is given
+fn link(args: &[String]) -> bool {
+    let mut inputs = vec![];
+    let mut output = None;
+    let mut strip = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            "--strip" => strip = true,
+            path => inputs.push(path),
+        }
+    }
+    let output = match output {
+        Some(output) if !inputs.is_empty() => output,
+        _ => {
+            eprintln!("Usage: alvm link <object>... -o <program> [--strip]");
+            return false;
+        }
+    };
+
+    let mut objects = vec![];
+    for path in inputs {
+        let object = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str::<Object>(&json).map_err(|e| e.to_string()));
+        match object {
+            Ok(object) => objects.push(object),
+            Err(e) => {
+                eprintln!("Unable to read object {}: {}", path, e);
+                return false;
+            }
+        }
+    }
+    let mut program = match linker::link(&objects) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    if !strip {
+        program = debug::add_debug_section(&program, &linker::link_debug_info(&objects));
+    }
+    match fs::write(output, program) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Unable to write {}: {}", output, e);
+            false
+        }
+    }
+}
+
+// `alvm run <program>` executes a linked program until it halts or faults
+fn run(args: &[String]) -> bool {
+    let path = match args {
+        [path] => path,
+        _ => {
+            eprintln!("Usage: alvm run <program>");
+            return false;
+        }
+    };
+    let program = match fs::read(path) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", path, e);
+            return false;
+        }
+    };
+    let (code, debug) = debug::split_debug_section(&program);
+    let mut vm = VM::default();
+    vm.add_bytes(code);
+    vm.run();
+    match vm.fault() {
+        Some(fault) => {
+            let fault = match debug {
+                Some(debug) => debug.describe(fault),
+                None => fault.to_string(),
+            };
+            eprintln!("Program faulted: {}", fault);
+            false
+        }
+        None => true,
+    }
+}
 
 fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
     let mut repl = repl::REPL::default();
-    repl.run();
+    // leading `-I <dir>` pairs add directories searched by `.include`
+    let mut include_dirs = vec![];
+    while args.len() >= 2 && args[0] == "-I" {
+        include_dirs.push(PathBuf::from(&args[1]));
+        args.drain(..2);
+    }
+    for dir in &include_dirs {
+        repl.add_include_dir(dir.clone());
+    }
+    let succeeded = match args.first().map(String::as_str) {
+        Some("assemble") => assemble(&args[1..], include_dirs),
+        Some("link") => link(&args[1..]),
+        Some("run") => run(&args[1..]),
+        Some("--listen") => listen(&args),
+        Some("--json") => match repl::json::run_json(io::stdin().lock(), io::stdout()) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("JSON session failed: {}", e);
+                false
+            }
+        },
+        Some(path) => match File::open(path) {
+            Ok(file) => repl.run_script(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Unable to open {}: {}", path, e);
+                false
+            }
+        },
+        None if !io::stdin().is_terminal() => repl.run_script(io::stdin().lock()),
+        None => {
+            repl.run();
+            true
+        }
+    };
+
+    if !succeeded {
+        process::exit(1);
+    }
 }
@@ -0,0 +1,138 @@
+use crate::assembler::register_parsers::REGISTER_ALIASES;
+use crate::instruction::{OPCODES, REGISTER_COUNT};
+use crate::repl::COMMANDS;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+
+// rustyline helper providing tab completion of REPL commands, opcode
+// mnemonics and register names
+pub struct REPLHelper {
+    mnemonics: Vec<String>,
+    // names given to registers with `.reg`, kept up to date by the REPL
+    register_aliases: Vec<String>,
+}
+
+impl Default for REPLHelper {
+    fn default() -> Self {
+        let mnemonics = OPCODES
+            .iter()
+            .map(|info| info.mnemonic.to_string())
+            .collect();
+        REPLHelper {
+            mnemonics,
+            register_aliases: vec![],
+        }
+    }
+}
+
+impl REPLHelper {
+    /// Replaces the `.reg` names offered alongside the ABI register names.
+    pub fn set_register_aliases(&mut self, names: Vec<String>) {
+        self.register_aliases = names;
+    }
+
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+
+        let candidates: Vec<String> = if word.starts_with('$') {
+            let registers = (0..REGISTER_COUNT).map(|i| format!("${}", i));
+            registers
+                .chain((0..REGISTER_COUNT).map(|i| format!("$f{}", i)))
+                .chain(
+                    REGISTER_ALIASES
+                        .iter()
+                        .map(|(name, _)| format!("${}", name)),
+                )
+                .chain(
+                    self.register_aliases
+                        .iter()
+                        .map(|name| format!("${}", name)),
+                )
+                .collect()
+        } else if start == 0 && word.starts_with('.') {
+            COMMANDS.iter().map(|(c, _)| c.to_string()).collect()
+        } else if line[..start].trim().is_empty() || line[..start].trim_end().ends_with(':') {
+            self.mnemonics.clone()
+        } else {
+            vec![]
+        };
+
+        let matches = candidates
+            .into_iter()
+            .filter(|c| c.starts_with(word))
+            .collect();
+        (start, matches)
+    }
+}
+
+impl Completer for REPLHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for REPLHelper {
+    type Hint = String;
+}
+
+impl Highlighter for REPLHelper {}
+
+impl Validator for REPLHelper {}
+
+impl Helper for REPLHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_commands() {
+        let helper = REPLHelper::default();
+        let (start, candidates) = helper.candidates(".cl", 3);
+        assert_eq!(start, 0);
+        assert_eq!(candidates, vec![".clear_program", ".clear_registers"]);
+
+        let (_, candidates) = helper.candidates(".fl", 3);
+        assert_eq!(candidates, vec![".float_registers", ".flags"]);
+    }
+
+    #[test]
+    fn test_complete_mnemonics() {
+        let helper = REPLHelper::default();
+        let (start, candidates) = helper.candidates("loop: jm", 8);
+        assert_eq!(start, 6);
+        assert_eq!(
+            candidates,
+            vec!["jmp", "jmpf", "jmpb", "jmpe", "jmpr", "jmpre", "jmpi", "jmpei"]
+        );
+
+        let (_, candidates) = helper.candidates("load $0 l", 9);
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn test_complete_registers() {
+        let helper = REPLHelper::default();
+        let (start, candidates) = helper.candidates("add $0 $3", 9);
+        assert_eq!(start, 7);
+        assert_eq!(candidates, vec!["$3", "$30", "$31"]);
+
+        let (_, candidates) = helper.candidates("addf64 $f3", 10);
+        assert_eq!(candidates, vec!["$f3", "$f30", "$f31"]);
+
+        let (_, candidates) = helper.candidates("add $t0 $s", 10);
+        assert_eq!(candidates, vec!["$sp"]);
+
+        let mut helper = REPLHelper::default();
+        helper.set_register_aliases(vec!["sum".to_string()]);
+        let (_, candidates) = helper.candidates("add $t0 $s", 10);
+        assert_eq!(candidates, vec!["$sp", "$sum"]);
+    }
+}
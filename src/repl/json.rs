@@ -0,0 +1,253 @@
+use crate::repl::REPL;
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+/// One request per line, e.g. `{"cmd": "exec", "src": "load $0 #1"}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    // assemble `src`, append it to the program and execute it
+    Exec { src: String },
+    // run from the current pc until the VM halts
+    Run,
+    // report the VM state without changing it
+    State,
+    // start over as a fresh REPL writing to the same output
+    Reset,
+}
+
+#[derive(Debug, Serialize)]
+struct Flags {
+    equal: bool,
+    remainder: i32,
+    halted: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct FaultInfo {
+    pc: usize,
+    message: String,
+}
+
+/// Reply to every request, always carrying the full VM state.
+#[derive(Debug, Serialize)]
+struct Response {
+    ok: bool,
+    registers: [i32; 32],
+    float_registers: [f64; 32],
+    pc: usize,
+    flags: Flags,
+    faults: Vec<FaultInfo>,
+    errors: Vec<String>,
+    output: String,
+}
+
+// REPL output sink whose contents are returned with each response
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serves line-delimited JSON requests from `reader`, writing one JSON
+/// response line per request to `writer`.
+pub fn run_json<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::Result<()> {
+    let buffer = SharedBuffer::default();
+    let mut repl = REPL::with_output(Box::new(buffer.clone()));
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = repl.handle_json(&line, &buffer);
+        writeln!(writer, "{}", response)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+impl REPL {
+    fn handle_json(&mut self, request: &str, buffer: &SharedBuffer) -> String {
+        // only what this request produced is reported
+        self.errors.clear();
+        self.faults.clear();
+
+        match serde_json::from_str::<Request>(request) {
+            Ok(Request::Exec { src }) => self.assemble_and_execute(&src),
+            Ok(Request::Run) => self.run_until_halt(),
+            Ok(Request::State) => {}
+            Ok(Request::Reset) => *self = REPL::with_output(Box::new(buffer.clone())),
+            Err(e) => self.errors.push(format!("Invalid request: {}", e)),
+        }
+
+        let faults: Vec<FaultInfo> = self
+            .faults
+            .iter()
+            .map(|fault| FaultInfo {
+                pc: fault.pc(),
+                message: self.assembler.debug.describe(fault),
+            })
+            .collect();
+        self.faults.clear();
+        let errors = std::mem::take(&mut self.errors);
+        let response = Response {
+            ok: errors.is_empty() && faults.is_empty(),
+            registers: self.vm.registers,
+            float_registers: self.vm.float_registers,
+            pc: self.vm.pc(),
+            flags: Flags {
+                equal: self.vm.equal_flag(),
+                remainder: self.vm.remainder(),
+                halted: self.vm.halted(),
+            },
+            faults,
+            errors,
+            output: buffer.take(),
+        };
+        serde_json::to_string(&response).expect("responses are always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::io::Cursor;
+
+    fn responses(requests: &str) -> Vec<Value> {
+        let mut output = vec![];
+        run_json(Cursor::new(requests), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_json_exec() {
+        let responses = responses(
+            r#"{"cmd": "exec", "src": "load $0 #5\nload $1 #7\nadd $0 $1 $2"}
+{"cmd": "state"}"#,
+        );
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["ok"], true);
+        assert_eq!(responses[0]["registers"][2], 12);
+        assert_eq!(responses[0]["pc"], 12);
+        assert_eq!(
+            responses[0]["output"],
+            "0000: load $0 #5\n0004: load $1 #7\n0008: add $0 $1 $2\n"
+        );
+        assert_eq!(responses[1]["output"], "");
+        assert_eq!(responses[1]["registers"][2], 12);
+        assert_eq!(responses[1]["float_registers"][0], 0.0);
+    }
+
+    #[test]
+    fn test_json_faults_and_errors() {
+        let responses = responses(
+            r#"{"cmd": "exec", "src": "div $0 $1 $2"}
+{"cmd": "exec", "src": "load $0 @nowhere"}
+{"cmd": "launch"}
+{"cmd": "reset"}"#,
+        );
+        assert_eq!(responses[0]["ok"], false);
+        assert_eq!(responses[0]["faults"][0]["pc"], 0);
+        assert_eq!(
+            responses[0]["faults"][0]["message"],
+            "division by zero at pc 0"
+        );
+        assert_eq!(responses[1]["errors"][0], "Label nowhere is not defined");
+        assert_eq!(responses[1]["faults"].as_array().unwrap().len(), 0);
+        assert_eq!(responses[2]["ok"], false);
+        assert_eq!(responses[3]["ok"], true);
+        assert_eq!(responses[3]["pc"], 0);
+    }
+
+    #[test]
+    fn test_json_keeps_no_old_failures() {
+        let buffer = SharedBuffer::default();
+        let mut repl = REPL::with_output(Box::new(buffer.clone()));
+        for _ in 0..3 {
+            repl.handle_json(r#"{"cmd": "exec", "src": "div $0 $1 $2\nigl"}"#, &buffer);
+        }
+        assert!(repl.errors.is_empty());
+        assert!(repl.faults.is_empty());
+    }
+
+    #[test]
+    fn test_json_reset_inside_block() {
+        let buffer = SharedBuffer::default();
+        let mut repl = REPL::with_output(Box::new(buffer.clone()));
+        repl.handle_json(
+            r#"{"cmd": "exec", "src": ".reg count $0\nload $0 #3"}"#,
+            &buffer,
+        );
+        repl.execute_line(".begin");
+        repl.execute_line("load $1 #4");
+        repl.handle_json(r#"{"cmd": "reset"}"#, &buffer);
+        assert!(repl.block_end.is_none());
+        assert!(repl.pending_lines.is_empty());
+        assert!(repl.command_buffer.is_empty());
+
+        let response = repl.handle_json(r#"{"cmd": "exec", "src": "load $count #1"}"#, &buffer);
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["errors"][0], "Register $count is not defined");
+        assert_eq!(response["registers"][0], 0);
+        assert_eq!(response["registers"][1], 0);
+    }
+
+    #[test]
+    fn test_json_run() {
+        let responses = responses(
+            r#"{"cmd": "exec", "src": "load $0 #1\nhlt\nload $1 #2"}
+{"cmd": "run"}"#,
+        );
+        assert_eq!(responses[0]["flags"]["halted"], true);
+        assert_eq!(responses[0]["registers"][1], 0);
+        assert_eq!(responses[1]["flags"]["halted"], false);
+        assert_eq!(responses[1]["registers"][1], 2);
+        assert_eq!(responses[1]["output"], "Executed 1 instructions, pc 12\n");
+    }
+
+    #[test]
+    fn test_json_fault_location() {
+        let dir = std::env::temp_dir().join(format!("alvm_json_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("loop.asm");
+        std::fs::write(&path, "load $0 #1\nloop:\n    div $0 $1 $2\n").unwrap();
+
+        let request = serde_json::json!({
+            "cmd": "exec",
+            "src": format!(".include {:?}", path.display().to_string()),
+        });
+        let responses = responses(&request.to_string());
+        let location = format!("{}:3:5", path.display());
+        assert_eq!(
+            responses[0]["faults"][0]["message"],
+            format!("division by zero at {} (loop)", location)
+        );
+        assert!(responses[0]["output"]
+            .as_str()
+            .unwrap()
+            .contains(&format!("0004: div $0 $1 $2  ; {}", location)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
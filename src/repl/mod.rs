@@ -1,54 +1,754 @@
-use crate::assembler::program_parsers::program;
-use crate::vm::VM;
-use std;
-use std::io;
-use std::io::Write;
+pub mod helper;
+pub mod json;
+pub mod registers;
+pub mod server;
+
+use crate::assembler::macros::MacroTable;
+use crate::assembler::register_parsers::any_register;
+use crate::assembler::{Assembler, Token};
+use crate::disassembler::{disassemble, disassemble_at};
+use crate::instruction::{Operand, OPCODES, REGISTER_COUNT};
+use crate::repl::helper::REPLHelper;
+use crate::vm::{Fault, VM};
+use nom::types::CompleteStr;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::env;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+// writes a line of session output; write errors are ignored since a broken
+// remote connection is noticed by the reading side
+macro_rules! out {
+    ($repl:expr, $($arg:tt)*) => {{
+        let _ = writeln!($repl.output, $($arg)*);
+    }};
+}
+
+/// REPL commands and what they do, as listed by `.help`.
+pub const COMMANDS: &[(&str, &str)] = &[
+    (".help", "list commands and opcodes"),
+    (".quit", "leave the REPL"),
+    (".history", "show the lines entered so far"),
+    (
+        ".registers",
+        "show registers, optionally a selection like $0-$3 $7",
+    ),
+    (
+        ".float_registers",
+        "show float registers, optionally a selection like $f0-$f3",
+    ),
+    (
+        ".load_file",
+        "assemble a file and append it, .run executes it",
+    ),
+    (
+        ".include_dir",
+        "add a directory searched by .include, or list them",
+    ),
+    (".program", "disassemble the program"),
+    (".run", "run from the pc until the VM halts"),
+    (
+        ".clear_program",
+        "remove the program, its labels, names and macros",
+    ),
+    (".clear_registers", "set every register to zero"),
+    (".pc", "show the program counter"),
+    (".flags", "show the equal flag and remainder"),
+    (".symbols", "list labels, constants and register names"),
+    (".begin", "start a block of lines assembled together"),
+    (".end", "assemble and run the open block"),
+    (".macro", "define a macro, e.g. .macro name a, b ... .endm"),
+    (".endm", "end the macro being defined"),
+    (".equ", "define a constant, e.g. .equ SIZE 16"),
+    (".define", "the same as .equ"),
+    (".reg", "name a register, e.g. .reg count $t0"),
+    (
+        ".include",
+        "assemble and run a file, e.g. .include \"lib.asm\"",
+    ),
+    (
+        ".expect",
+        "check a register value, e.g. .expect $0 5 or .expect $f0 1.5",
+    ),
+];
+
+// stop echoing newly entered code after this many steps, so a backwards
+// jump can't hang the prompt; `.run` has no such limit outside remote
+// sessions
+const MAX_ECHO_STEPS: usize = 1000;
+// steps `.run` may take in a remote session, so a looping program can't
+// keep a server thread busy forever
+const MAX_REMOTE_STEPS: usize = 1_000_000;
+/// Longest line a remote session accepts, so a client can't make the
+/// server buffer without bound.
+pub const MAX_LINE_LEN: usize = 4096;
+
+// destination of everything the REPL prints
+struct Output(Box<dyn Write + Send>);
+
+impl Default for Output {
+    fn default() -> Self {
+        Output(Box::new(io::stdout()))
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct REPL {
     command_buffer: Vec<String>,
     vm: VM,
+    assembler: Assembler,
+    // assembly lines collected by `.begin`, `.macro` or a trailing backslash,
+    // assembled together once the block is complete
+    pending_lines: Vec<String>,
+    // the line that closes the open block, `.end` or `.endm`
+    block_end: Option<&'static str>,
+    // failures of the current command, used to fail scripted runs and for
+    // JSON responses
+    errors: Vec<String>,
+    faults: Vec<Fault>,
+    // line being executed by `run_script`, for error messages
+    script_line: Option<usize>,
+    output: Output,
+    // remote sessions may not read files on the host
+    remote: bool,
+    // registers before the previous command, so `.registers` can mark what
+    // it changed
+    previous_registers: [i32; REGISTER_COUNT],
+    // highlight with ANSI colours, only when talking to a terminal
+    color: bool,
 }
 
 impl REPL {
+    pub fn with_output(output: Box<dyn Write + Send>) -> REPL {
+        REPL {
+            output: Output(output),
+            ..REPL::default()
+        }
+    }
+
     pub fn run(&mut self) {
-        println!("welcome to alvm!");
+        self.color = io::stdout().is_terminal();
+        out!(self, "welcome to alvm!");
+        let mut editor = Editor::<REPLHelper>::new();
+        editor.set_helper(Some(REPLHelper::default()));
+        let history = REPL::history_path();
+        if let Some(path) = &history {
+            // a missing history file just means this is the first session
+            let _ = editor.load_history(path);
+        }
+
         loop {
-            let mut buffer = String::new();
-            let stdin = io::stdin();
+            let prompt = if self.block_end.is_some() || !self.pending_lines.is_empty() {
+                "..."
+            } else {
+                ">>>"
+            };
+            let buffer = match editor.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    if self.block_end.is_some() || !self.pending_lines.is_empty() {
+                        out!(self, "Discarded {} pending lines", self.pending_lines.len());
+                        self.pending_lines.clear();
+                        self.block_end = None;
+                    }
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    out!(self, "Unable to read line from user: {}", e);
+                    break;
+                }
+            };
+            let buffer = buffer.trim();
+            if !buffer.is_empty() {
+                editor.add_history_entry(buffer);
+            }
+            if !self.execute_line(buffer) {
+                break;
+            }
+            if let Some(helper) = editor.helper_mut() {
+                let aliases = self.assembler.symbols.registers().iter();
+                helper.set_register_aliases(aliases.map(|alias| alias.name.clone()).collect());
+            }
+        }
 
-            print!(">>>");
-            io::stdout().flush().expect("Unable to flush stdout");
+        if let Some(path) = &history {
+            if let Err(e) = editor.save_history(path) {
+                out!(self, "Unable to save history to {}: {}", path.display(), e);
+            }
+        }
+    }
 
-            stdin
-                .read_line(&mut buffer)
-                .expect("Unable to read line from user");
-            let buffer = buffer.trim();
-            self.command_buffer.push(buffer.to_string());
-            match buffer {
-                ".quit" => {
-                    println!("bye~~!");
-                    std::process::exit(0);
-                }
-                ".history" => {
-                    for command in &self.command_buffer {
-                        println!("{}", command);
+    /// Adds a directory searched by `.include` in assembled code.
+    pub fn add_include_dir(&mut self, dir: PathBuf) {
+        self.assembler.include_dirs.push(dir);
+    }
+
+    /// Serves a prompted session over `reader` and the REPL output, as used
+    /// for remote connections. Host files can't be loaded from such a
+    /// session, and it ends at a line longer than `MAX_LINE_LEN`.
+    pub fn run_session<R: BufRead>(&mut self, mut reader: R) {
+        self.remote = true;
+        self.assembler.deny_includes = true;
+        out!(self, "welcome to alvm!");
+        loop {
+            let prompt = if self.block_end.is_some() || !self.pending_lines.is_empty() {
+                "..."
+            } else {
+                ">>>"
+            };
+            let _ = write!(self.output, "{}", prompt);
+            let _ = self.output.flush();
+
+            let mut line = vec![];
+            let mut limited = reader.by_ref().take(MAX_LINE_LEN as u64 + 1);
+            match limited.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            if line.len() > MAX_LINE_LEN && !line.ends_with(b"\n") {
+                out!(self, "Lines are at most {} bytes", MAX_LINE_LEN);
+                break;
+            }
+            let line = match String::from_utf8(line) {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if !self.execute_line(line.trim()) {
+                break;
+            }
+        }
+        let _ = self.output.flush();
+    }
+
+    /// Runs commands from `reader` without prompting, e.g. a script file or
+    /// piped stdin. Returns false if any command failed, including `.expect`
+    /// checks, assembler errors and VM faults.
+    pub fn run_script<R: BufRead>(&mut self, reader: R) -> bool {
+        let mut failed = false;
+        for (number, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    self.report_error(format!("Unable to read script: {}", e));
+                    break;
+                }
+            };
+            self.script_line = Some(number + 1);
+            let line = line.trim();
+            // comment lines and blank lines only matter to the reader
+            if line.starts_with(';') || (line.is_empty() && self.pending_lines.is_empty()) {
+                continue;
+            }
+            let keep_going = self.execute_line(line);
+            failed |= self.failed();
+            if !keep_going {
+                break;
+            }
+        }
+        self.script_line = None;
+
+        if self.block_end.is_some() || !self.pending_lines.is_empty() {
+            self.report_error("Script ended inside a multi-line block".to_string());
+            self.pending_lines.clear();
+            self.block_end = None;
+        }
+        !failed && !self.failed()
+    }
+
+    fn failed(&self) -> bool {
+        !self.errors.is_empty() || !self.faults.is_empty()
+    }
+
+    // handles one line of input, returning false once the session should end
+    fn execute_line(&mut self, buffer: &str) -> bool {
+        self.errors.clear();
+        self.faults.clear();
+        let registers = self.vm.registers;
+        let keep_going = self.execute_command(buffer);
+        self.previous_registers = registers;
+        keep_going
+    }
+
+    fn execute_command(&mut self, buffer: &str) -> bool {
+        self.command_buffer.push(buffer.to_string());
+
+        if let Some(end) = self.block_end {
+            // a macro definition reaches the assembler whole, `.endm` included
+            if buffer != end || end == ".endm" {
+                self.pending_lines.push(buffer.to_string());
+            }
+            if buffer == end {
+                self.block_end = None;
+                self.assemble_pending();
+            }
+            return true;
+        }
+        if buffer.ends_with('\\') {
+            let line = buffer.trim_end_matches('\\');
+            self.pending_lines.push(line.to_string());
+            return true;
+        }
+        if !self.pending_lines.is_empty() {
+            self.pending_lines.push(buffer.to_string());
+            self.assemble_pending();
+            return true;
+        }
+
+        let mut args = buffer.split_whitespace();
+        match args.next().unwrap_or("") {
+            ".quit" => {
+                out!(self, "bye~~!");
+                return false;
+            }
+            ".history" => {
+                for command in &self.command_buffer {
+                    out!(self, "{}", command);
+                }
+            }
+            ".registers" => match registers::parse_selection(args, "$", self.vm.registers.len()) {
+                Ok(selection) => {
+                    let rows = registers::format_table(
+                        &self.vm.registers,
+                        &self.previous_registers,
+                        &selection,
+                        self.color,
+                    );
+                    for row in rows {
+                        out!(self, "{}", row);
                     }
                 }
-                ".registers" => println!("{:#?}", self.vm.registers),
-                _ => {
-                    let program = match program(buffer.into()) {
-                        Ok((_, program)) => program,
-                        Err(_) => {
-                            println!("Unable to parse input");
-                            continue;
+                Err(e) => self.report_error(e),
+            },
+            ".float_registers" => {
+                let count = self.vm.float_registers.len();
+                match registers::parse_selection(args, "$f", count) {
+                    Ok(selection) => {
+                        let rows =
+                            registers::format_float_table(&self.vm.float_registers, &selection);
+                        for row in rows {
+                            out!(self, "{}", row);
                         }
-                    };
+                    }
+                    Err(e) => self.report_error(e),
+                }
+            }
+            ".load_file" if self.remote => {
+                self.report_error("Loading files is disabled in remote sessions".to_string());
+            }
+            ".load_file" => match args.next() {
+                Some(path) => self.load_file(path),
+                None => self.report_error("Usage: .load_file <path>".to_string()),
+            },
+            ".include_dir" if self.remote => {
+                self.report_error("Including files is disabled in remote sessions".to_string());
+            }
+            ".include_dir" => match args.next() {
+                Some(dir) => self.add_include_dir(PathBuf::from(dir)),
+                None => {
+                    for dir in &self.assembler.include_dirs {
+                        out!(self, "{}", dir.display());
+                    }
+                }
+            },
+            ".help" => self.print_help(),
+            ".program" => self.print_program(),
+            ".run" => self.run_until_halt(),
+            ".clear_program" => {
+                self.vm.clear_program();
+                self.assembler.symbols.clear();
+                self.assembler.macros = MacroTable::default();
+                self.assembler.debug.clear();
+                out!(self, "Program cleared");
+            }
+            ".clear_registers" => {
+                self.vm.registers = [0; REGISTER_COUNT];
+                self.vm.float_registers = [0.0; REGISTER_COUNT];
+                out!(self, "Registers cleared");
+            }
+            ".pc" => {
+                let pc = self.vm.pc();
+                out!(self, "{}{}", pc, self.source_suffix(pc));
+            }
+            ".flags" => {
+                out!(self, "equal_flag: {}", self.vm.equal_flag());
+                out!(self, "remainder: {}", self.vm.remainder());
+            }
+            ".begin" => self.block_end = Some(".end"),
+            ".end" => self.report_error("No .begin block is open".to_string()),
+            ".macro" => {
+                self.pending_lines.push(buffer.to_string());
+                self.block_end = Some(".endm");
+            }
+            ".endm" => self.report_error("No .macro block is open".to_string()),
+            ".include" | ".equ" | ".define" | ".reg" => self.assemble_and_execute(buffer),
+            ".symbols" => {
+                let symbols = &self.assembler.symbols;
+                if symbols.symbols().is_empty()
+                    && symbols.constants().is_empty()
+                    && symbols.registers().is_empty()
+                {
+                    out!(self, "No symbols defined");
+                }
+                for symbol in symbols.symbols() {
+                    out!(self, "{}: {}", symbol.name, symbol.offset);
+                }
+                for constant in symbols.constants() {
+                    out!(self, "{} = {}", constant.name, constant.value);
+                }
+                for alias in symbols.registers() {
+                    out!(self, "${} = {}", alias.name, alias.register);
+                }
+            }
+            ".expect" => match (args.next(), args.next()) {
+                (Some(register), Some(value)) => self.expect_register(register, value),
+                _ => self.report_error("Usage: .expect $<register> <value>".to_string()),
+            },
+            "" => {}
+            command if command.starts_with('.') => {
+                self.report_error(format!("Unknown command: {}", command));
+            }
+            _ => self.assemble_and_execute(buffer),
+        }
+
+        true
+    }
+
+    fn report_error(&mut self, message: String) {
+        self.print_error(&message);
+        self.errors.push(message);
+    }
+
+    fn print_error(&mut self, message: &str) {
+        match self.script_line {
+            Some(line) => out!(self, "line {}: {}", line, message),
+            None => out!(self, "{}", message),
+        }
+    }
+
+    // `register` may be a number or an ABI or `.reg` name, as in assembly
+    fn expect_register(&mut self, register: &str, value: &str) {
+        let operand = match any_register(CompleteStr(register)) {
+            Ok((rest, token)) if rest.is_empty() => match token {
+                Token::Register { reg_num } => Some(Operand::Reg(reg_num)),
+                Token::FloatRegister { reg_num } => Some(Operand::FReg(reg_num)),
+                Token::RegisterAlias { name } => self.assembler.symbols.register(&name).cloned(),
+                _ => None,
+            },
+            _ => None,
+        };
+        match operand {
+            Some(Operand::Reg(index)) if usize::from(index) < REGISTER_COUNT => {
+                self.expect_integer_register(usize::from(index), value)
+            }
+            Some(Operand::FReg(index)) if usize::from(index) < REGISTER_COUNT => {
+                self.expect_float_register(usize::from(index), value)
+            }
+            _ => self.report_error(format!("Invalid register: {}", register)),
+        }
+    }
+
+    fn expect_integer_register(&mut self, index: usize, value: &str) {
+        let expected = match value.parse::<i32>() {
+            Ok(expected) => expected,
+            Err(_) => {
+                self.report_error(format!("Invalid value: {}", value));
+                return;
+            }
+        };
+
+        let actual = self.vm.registers[index];
+        if actual != expected {
+            self.report_error(format!(
+                "Expectation failed: ${} is {}, expected {}",
+                index, actual, expected
+            ));
+        }
+    }
+
+    fn expect_float_register(&mut self, index: usize, value: &str) {
+        let expected = match value.parse::<f64>() {
+            Ok(expected) => expected,
+            Err(_) => {
+                self.report_error(format!("Invalid value: {}", value));
+                return;
+            }
+        };
+
+        let actual = self.vm.float_registers[index];
+        if actual != expected {
+            self.report_error(format!(
+                "Expectation failed: $f{} is {:?}, expected {:?}",
+                index, actual, expected
+            ));
+        }
+    }
+
+    fn history_path() -> Option<PathBuf> {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".alvm_history"))
+    }
+
+    fn assemble_pending(&mut self) {
+        let source = self.pending_lines.join("\n");
+        self.pending_lines.clear();
+        self.assemble_and_execute(&source);
+    }
+
+    fn assemble_and_execute(&mut self, source: &str) {
+        let start = self.vm.program().len();
+        match self.assembler.assemble(source, start) {
+            Ok(bytes) if bytes.is_empty() => {}
+            Ok(bytes) => {
+                self.vm.add_bytes(&bytes);
+                self.execute_new_code(start);
+            }
+            Err(e) => self.report_error(e.to_string()),
+        }
+    }
+
+    fn load_file(&mut self, path: &str) {
+        let offset = self.vm.program().len();
+        match self.assembler.assemble_file(Path::new(path), offset) {
+            Ok(bytes) => {
+                out!(self, "Loaded {} bytes from {}", bytes.len(), path);
+                self.vm.add_bytes(&bytes);
+            }
+            Err(e) => self.report_error(e.to_string()),
+        }
+    }
+
+    // runs the code just appended at `start`, echoing each executed
+    // instruction; code loaded earlier but never run is skipped, `.run`
+    // executes it
+    fn execute_new_code(&mut self, start: usize) {
+        self.vm.set_pc(start);
+        for _ in 0..MAX_ECHO_STEPS {
+            if self.vm.pc() >= self.vm.program().len() {
+                return;
+            }
+            let instruction = disassemble_at(self.vm.program(), self.vm.pc());
+            let suffix = self.source_suffix(instruction.offset);
+            out!(
+                self,
+                "{:04}: {}{}",
+                instruction.offset,
+                instruction.text,
+                suffix
+            );
+            if self.vm.run_once() {
+                self.report_stop();
+                return;
+            }
+        }
+        out!(
+            self,
+            "Stopped after {} instructions at {}, use .run to continue",
+            MAX_ECHO_STEPS,
+            self.assembler.debug.position(self.vm.pc())
+        );
+    }
 
-                    self.vm.program.append(&mut program.to_bytes());
-                    self.vm.run_once();
+    fn run_until_halt(&mut self) {
+        let mut executed = 0;
+        while self.vm.pc() < self.vm.program().len() {
+            if self.remote && executed == MAX_REMOTE_STEPS {
+                out!(
+                    self,
+                    "Stopped after {} instructions at {}, use .run to continue",
+                    MAX_REMOTE_STEPS,
+                    self.assembler.debug.position(self.vm.pc())
+                );
+                return;
+            }
+            executed += 1;
+            if self.vm.run_once() {
+                break;
+            }
+        }
+        out!(
+            self,
+            "Executed {} instructions, {}",
+            executed,
+            self.assembler.debug.position(self.vm.pc())
+        );
+        self.report_stop();
+    }
+
+    // reports why the VM stopped
+    fn report_stop(&mut self) {
+        if self.vm.halted() {
+            out!(self, "HLT encountered");
+        }
+        if let Some(fault) = self.vm.fault().cloned() {
+            let message = self.assembler.debug.describe(&fault);
+            self.print_error(&format!("Fault: {}", message));
+            self.faults.push(fault);
+        }
+    }
+
+    fn print_help(&mut self) {
+        out!(self, "Commands:");
+        for (command, description) in COMMANDS {
+            out!(self, "  {:<20} {}", command, description);
+        }
+        out!(self, "Opcodes:");
+        for info in OPCODES {
+            let usage = format!("{} {}", info.mnemonic, info.syntax());
+            out!(self, "  {:<20} {}", usage.trim_end(), info.description);
+        }
+    }
+
+    fn print_program(&mut self) {
+        for instruction in disassemble(self.vm.program()) {
+            for symbol in self.assembler.symbols.symbols() {
+                if symbol.offset == instruction.offset {
+                    out!(self, "{}:", symbol.name);
                 }
             }
+            let suffix = self.source_suffix(instruction.offset);
+            out!(
+                self,
+                "{:04}: {}{}",
+                instruction.offset,
+                instruction.text,
+                suffix
+            );
+        }
+    }
+
+    // where the instruction at `offset` was read from, to follow what is
+    // printed about it, or nothing for code typed at the prompt
+    fn source_suffix(&self, offset: usize) -> String {
+        match self.assembler.debug.line_at(offset) {
+            Some(entry) => format!("  ; {}", entry),
+            None => String::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_run_script() {
+        let mut repl = REPL::default();
+        let script = "; comment\nload $0 #5 load $1 #7\n\n.expect $0 5\n.expect $1 7\n";
+        assert!(repl.run_script(Cursor::new(script)));
+        assert_eq!(repl.vm.registers[1], 7);
+
+        let script = "load $fp #8\n.reg count $t1\nload $count #2\n\
+                      .expect $fp 8\n.expect $30 8\n.expect $count 2\n";
+        assert!(repl.run_script(Cursor::new(script)));
+    }
+
+    #[test]
+    fn test_run_script_failures() {
+        let mut repl = REPL::default();
+        assert!(!repl.run_script(Cursor::new("load $0 #5\n.expect $0 6\n")));
+
+        let mut repl = REPL::default();
+        assert!(!repl.run_script(Cursor::new("load $0 @missing\n")));
+
+        let mut repl = REPL::default();
+        assert!(!repl.run_script(Cursor::new("div $0 $1 $2\n")));
+
+        let mut repl = REPL::default();
+        assert!(!repl.run_script(Cursor::new(".expect $32 0\n")));
+        assert!(!repl.run_script(Cursor::new(".expect $missing 0\n")));
+
+        let mut repl = REPL::default();
+        assert!(!repl.run_script(Cursor::new(".begin\nhlt\n")));
+    }
+
+    #[test]
+    fn test_run_script_multi_line() {
+        let mut repl = REPL::default();
+        let script = "load $0 @end \\\njmp $0 \\\nload $1 #9 \\\nend: hlt\n.expect $1 0\n";
+        assert!(repl.run_script(Cursor::new(script)));
+        assert!(repl.assembler.symbols.has_symbol("end"));
+    }
+
+    #[test]
+    fn test_run_script_macros() {
+        let mut repl = REPL::default();
+        let script = ".macro loadc reg, value\nload \\reg \\value\n.endm\n\
+                      loadc $0, #5\nloadc $1, #7\n.expect $0 5\n.expect $1 7\n";
+        assert!(repl.run_script(Cursor::new(script)));
+        assert!(!repl.run_script(Cursor::new(".endm\n")));
+        assert!(repl.run_script(Cursor::new(".clear_program\n")));
+        assert!(!repl.run_script(Cursor::new("loadc $0, #1\n")));
+    }
+
+    #[test]
+    fn test_new_code_runs_from_where_it_was_added() {
+        let path = env::temp_dir().join(format!("alvm_repl_load_{}.asm", std::process::id()));
+        std::fs::write(&path, "load $0 #7\n").unwrap();
+        let mut repl = REPL::default();
+        let script = format!(".load_file {}\n.equ ONE 1\nload $1 #ONE\n", path.display());
+        assert!(repl.run_script(Cursor::new(script)));
+        assert_eq!(repl.vm.registers[0], 0);
+        assert_eq!(repl.vm.registers[1], 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_run_script_stops_at_quit() {
+        let mut repl = REPL::default();
+        assert!(repl.run_script(Cursor::new("load $0 #1\n.quit\nload $0 #2\n")));
+        assert_eq!(repl.vm.registers[0], 1);
+    }
+
+    #[test]
+    fn test_previous_registers_track_last_command() {
+        let mut repl = REPL::default();
+        assert!(repl.run_script(Cursor::new("load $0 #5\nload $1 #6\n")));
+        assert_eq!(repl.previous_registers[0], 5);
+        assert_eq!(repl.previous_registers[1], 0);
+        assert!(repl.run_script(Cursor::new(".pc\n")));
+        assert_eq!(repl.previous_registers[1], 6);
+        assert!(repl.run_script(Cursor::new(".registers $0-$1\n")));
+        assert!(!repl.run_script(Cursor::new(".registers $1-$0\n")));
+    }
+
+    #[test]
+    fn test_help_and_operand_checks() {
+        let mut repl = REPL::default();
+        assert!(repl.run_script(Cursor::new(".help\n")));
+        assert!(!repl.run_script(Cursor::new("add $0 $1\n")));
+        assert_eq!(
+            repl.errors.last().unwrap(),
+            "Invalid operands, expected: add $a $b $c"
+        );
+    }
+
+    #[test]
+    fn test_float_registers() {
+        let mut repl = REPL::default();
+        let script = "loadf64 $f0 #2.5\nload $0 #4\nitof $0 $f1\nmulf64 $f0 $f1 $f2\n\
+                      .expect $f2 10\n.float_registers $f0-$f2\n";
+        assert!(repl.run_script(Cursor::new(script)));
+        assert!(!repl.run_script(Cursor::new(".expect $f2 10.5\n")));
+        assert!(repl.run_script(Cursor::new("loadf64 $f3 #-0.1\n.expect $f3 -0.1\n")));
+        assert!(!repl.run_script(Cursor::new(".float_registers $0\n")));
+    }
+}
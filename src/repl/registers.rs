@@ -0,0 +1,151 @@
+// formatting for the `.registers` command
+
+const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// Parses `.registers` arguments such as `$0-$7 $12` into register
+/// indexes, each register written as `prefix` followed by its number. No
+/// arguments selects every register.
+pub fn parse_selection<'a, I>(args: I, prefix: &str, count: usize) -> Result<Vec<usize>, String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut selection = vec![];
+    for arg in args {
+        let (first, last) = match arg.find('-') {
+            Some(i) => (
+                parse_register(&arg[..i], prefix, count)?,
+                parse_register(&arg[i + 1..], prefix, count)?,
+            ),
+            None => {
+                let register = parse_register(arg, prefix, count)?;
+                (register, register)
+            }
+        };
+        if first > last {
+            return Err(format!("Invalid register range: {}", arg));
+        }
+        selection.extend(first..=last);
+    }
+
+    if selection.is_empty() {
+        selection.extend(0..count);
+    }
+    Ok(selection)
+}
+
+fn parse_register(text: &str, prefix: &str, count: usize) -> Result<usize, String> {
+    match text.strip_prefix(prefix).map(str::parse::<usize>) {
+        Some(Ok(register)) if register < count => Ok(register),
+        _ => Err(format!("Invalid register: {}", text)),
+    }
+}
+
+/// One row per selected register with its signed, unsigned and hex value.
+/// Registers that differ from `previous` are marked with `*`, and also
+/// coloured when `color` is set.
+pub fn format_table(
+    registers: &[i32],
+    previous: &[i32],
+    selection: &[usize],
+    color: bool,
+) -> Vec<String> {
+    let mut rows = vec![format!(
+        "{:<4} {:>11} {:>10} {:>10}",
+        "reg", "signed", "unsigned", "hex"
+    )];
+    for &register in selection {
+        let value = registers[register];
+        let row = format!(
+            "{:<4} {:>11} {:>10} {:#010x}",
+            format!("${}", register),
+            value,
+            value as u32,
+            value as u32
+        );
+        let changed = value != previous[register];
+        rows.push(match (changed, color) {
+            (true, true) => format!("{}{} *{}", HIGHLIGHT, row, RESET),
+            (true, false) => format!("{} *", row),
+            (false, _) => row,
+        });
+    }
+    rows
+}
+
+/// One row per selected floating point register with its value and bits.
+pub fn format_float_table(registers: &[f64], selection: &[usize]) -> Vec<String> {
+    let mut rows = vec![format!("{:<4} {:>24} {:>18}", "reg", "value", "bits")];
+    for &register in selection {
+        let value = registers[register];
+        rows.push(format!(
+            "{:<4} {:>24?} {:#018x}",
+            format!("$f{}", register),
+            value,
+            value.to_bits()
+        ));
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(
+            parse_selection("".split_whitespace(), "$", 4),
+            Ok(vec![0, 1, 2, 3])
+        );
+        assert_eq!(
+            parse_selection("$0-$2 $7".split_whitespace(), "$", 32),
+            Ok(vec![0, 1, 2, 7])
+        );
+        assert!(parse_selection("$3-$1".split_whitespace(), "$", 32).is_err());
+        assert!(parse_selection("$32".split_whitespace(), "$", 32).is_err());
+        assert!(parse_selection("3".split_whitespace(), "$", 32).is_err());
+        assert_eq!(
+            parse_selection("$f1-$f2".split_whitespace(), "$f", 32),
+            Ok(vec![1, 2])
+        );
+        assert!(parse_selection("$1".split_whitespace(), "$f", 32).is_err());
+    }
+
+    #[test]
+    fn test_format_table() {
+        let registers = [5, -1, 0];
+        let previous = [0, -1, 0];
+        let rows = format_table(&registers, &previous, &[0, 1], false);
+        assert_eq!(
+            rows,
+            vec![
+                "reg       signed   unsigned        hex",
+                "$0             5          5 0x00000005 *",
+                "$1            -1 4294967295 0xffffffff",
+            ]
+        );
+
+        let rows = format_table(&registers, &previous, &[0], true);
+        assert_eq!(
+            rows[1],
+            format!(
+                "{}$0             5          5 0x00000005 *{}",
+                HIGHLIGHT, RESET
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_float_table() {
+        let rows = format_float_table(&[1.5, -0.0], &[0, 1]);
+        assert_eq!(
+            rows,
+            vec![
+                "reg                     value               bits",
+                "$f0                       1.5 0x3ff8000000000000",
+                "$f1                      -0.0 0x8000000000000000",
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use crate::repl::REPL;
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// time a client has to send the whole token line
+const AUTH_TIMEOUT: Duration = Duration::from_secs(30);
+// longest token the server accepts, so the token line can be bounded
+pub const MAX_TOKEN_LEN: usize = 1024;
+
+/// Serves REPL sessions over TCP, each connection getting its own VM.
+/// Clients must send the auth token as their first line.
+pub struct Server {
+    listener: TcpListener,
+    token: String,
+    max_connections: usize,
+    active: Arc<AtomicUsize>,
+}
+
+// decrements the active connection count when a session ends, however it ends
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        token: &str,
+        max_connections: usize,
+    ) -> io::Result<Server> {
+        if token.len() > MAX_TOKEN_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("tokens are at most {} bytes", MAX_TOKEN_LEN),
+            ));
+        }
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            token: token.to_string(),
+            max_connections,
+            active: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections forever, serving each on its own thread.
+    pub fn run(&self) {
+        for stream in self.listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Unable to accept connection: {}", e);
+                    continue;
+                }
+            };
+
+            if self.active.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+                self.active.fetch_sub(1, Ordering::SeqCst);
+                let _ = writeln!(stream, "Too many connections");
+                continue;
+            }
+            let guard = ConnectionGuard(Arc::clone(&self.active));
+            let token = self.token.clone();
+            thread::spawn(move || {
+                let _guard = guard;
+                if let Err(e) = Server::serve(stream, &token) {
+                    eprintln!("Remote session ended with an error: {}", e);
+                }
+            });
+        }
+    }
+
+    fn serve(mut stream: TcpStream, token: &str) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        write!(stream, "token: ")?;
+        stream.flush()?;
+
+        let line = Server::read_token_line(&stream, &mut reader)?;
+        let given = line
+            .as_deref()
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+        let given = given.and_then(|given| std::str::from_utf8(given).ok());
+        if !given.is_some_and(|given| tokens_match(given, token)) {
+            writeln!(stream, "Authentication failed")?;
+            return Ok(());
+        }
+        stream.set_read_timeout(None)?;
+
+        let mut repl = REPL::with_output(Box::new(stream));
+        repl.run_session(reader);
+        Ok(())
+    }
+
+    // reads the first line, without its newline, or None if the client
+    // doesn't send a short enough line before the deadline, so a silent or
+    // trickling client can't hold a connection slot
+    fn read_token_line(
+        stream: &TcpStream,
+        reader: &mut BufReader<TcpStream>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + AUTH_TIMEOUT;
+        let mut limited = reader.by_ref().take(MAX_TOKEN_LEN as u64 + 2);
+        let mut line = vec![];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            stream.set_read_timeout(Some(remaining))?;
+            let available = match limited.fill_buf() {
+                Ok(available) => available,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            // the connection closed or the line is too long
+            if available.is_empty() {
+                return Ok(None);
+            }
+            match available.iter().position(|&byte| byte == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&available[..end]);
+                    limited.consume(end + 1);
+                    return Ok(Some(line));
+                }
+                None => {
+                    let read = available.len();
+                    line.extend_from_slice(available);
+                    limited.consume(read);
+                }
+            }
+        }
+    }
+}
+
+// compares every byte so the time taken doesn't reveal a matching prefix
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::MAX_LINE_LEN;
+    use std::io::Read;
+
+    fn start_server(max_connections: usize) -> SocketAddr {
+        let server = Server::bind("127.0.0.1:0", "secret", max_connections).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    // connects and reads up to the token prompt, so the server has counted
+    // the connection by the time this returns
+    fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut prompt = [0; 7];
+        reader.read_exact(&mut prompt).unwrap();
+        assert_eq!(&prompt, b"token: ");
+        (stream, reader)
+    }
+
+    #[test]
+    fn test_remote_session() {
+        let addr = start_server(2);
+        let (mut stream, mut reader) = connect(addr);
+        stream
+            .write_all(b"secret\nload $0 #500\n.load_file /etc/passwd\n.pc\n")
+            .unwrap();
+        stream
+            .write_all(b".include \"/etc/passwd\"\n.quit\n")
+            .unwrap();
+
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert!(output.starts_with("welcome to alvm!\n>>>0000: load $0 #500\n"));
+        assert!(output.contains("Loading files is disabled in remote sessions"));
+        assert!(output.contains(">>>4\n"));
+        assert!(output.contains("Including files is disabled"));
+        assert!(output.ends_with("bye~~!\n"));
+    }
+
+    #[test]
+    fn test_remote_session_rejects_bad_token() {
+        let addr = start_server(2);
+        let (mut stream, mut reader) = connect(addr);
+        stream.write_all(b"guess\n").unwrap();
+
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "Authentication failed\n");
+    }
+
+    #[test]
+    fn test_remote_session_rejects_long_token() {
+        let addr = start_server(2);
+        let (mut stream, mut reader) = connect(addr);
+        // the server may close before reading everything
+        let _ = stream.write_all(&[b'a'; 4 * MAX_TOKEN_LEN]);
+
+        let mut output = vec![];
+        let _ = reader.read_to_end(&mut output);
+        assert_eq!(output, b"Authentication failed\n");
+    }
+
+    #[test]
+    fn test_remote_session_limits() {
+        let addr = start_server(2);
+        let (mut stream, mut reader) = connect(addr);
+        stream
+            .write_all(b"secret\n.begin\nloop: jmpr @loop\n.end\n.run\n")
+            .unwrap();
+        let mut line = String::new();
+        while !line.contains("Stopped after 1000000 instructions") {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        // one byte too many, all of which the server reads before closing
+        stream.write_all(&[b'a'; MAX_LINE_LEN + 1]).unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(
+            output,
+            format!(">>>Lines are at most {} bytes\n", MAX_LINE_LEN)
+        );
+    }
+
+    #[test]
+    fn test_remote_connection_limit() {
+        let addr = start_server(1);
+        let (mut first, mut first_reader) = connect(addr);
+
+        let mut second = TcpStream::connect(addr).unwrap();
+        let mut output = String::new();
+        second.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "Too many connections\n");
+
+        first.write_all(b"secret\n.quit\n").unwrap();
+        let mut output = String::new();
+        first_reader.read_to_string(&mut output).unwrap();
+        assert!(output.ends_with("bye~~!\n"));
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret1", "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
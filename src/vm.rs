@@ -1,154 +1,555 @@
 use crate::instruction::*;
+use half::f16;
+use std::fmt;
+
+/// Error raised while executing an instruction. `pc` is the offset of the
+/// faulting instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    IllegalOpcode { pc: usize, opcode: u8 },
+    InvalidRegister { pc: usize, register: u8 },
+    InvalidFloatRegister { pc: usize, register: u8 },
+    TruncatedInstruction { pc: usize },
+    DivideByZero { pc: usize },
+    InvalidJumpTarget { pc: usize, offset: i32 },
+}
+
+impl Fault {
+    pub fn pc(&self) -> usize {
+        match self {
+            Fault::IllegalOpcode { pc, .. }
+            | Fault::InvalidRegister { pc, .. }
+            | Fault::InvalidFloatRegister { pc, .. }
+            | Fault::TruncatedInstruction { pc }
+            | Fault::DivideByZero { pc }
+            | Fault::InvalidJumpTarget { pc, .. } => *pc,
+        }
+    }
+
+    /// What went wrong, without where.
+    pub fn reason(&self) -> String {
+        match self {
+            Fault::IllegalOpcode { opcode, .. } => format!("illegal opcode {}", opcode),
+            Fault::InvalidRegister { register, .. } => format!("invalid register ${}", register),
+            Fault::InvalidFloatRegister { register, .. } => {
+                format!("invalid register $f{}", register)
+            }
+            Fault::TruncatedInstruction { .. } => "truncated instruction".to_string(),
+            Fault::DivideByZero { .. } => "division by zero".to_string(),
+            Fault::InvalidJumpTarget { offset, .. } => {
+                format!("jump by {} out of range", offset)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at pc {}", self.reason(), self.pc())
+    }
+}
+
+// An instruction decoded once, with each register or immediate operand
+// widened to 16 bits, so it can be executed without allocating
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct DecodedOp {
+    opcode: Opcode,
+    operands: [u16; 3],
+}
+
+impl DecodedOp {
+    fn decode(bytes: &[u8]) -> Result<DecodedOp, DecodeError> {
+        let (instruction, _) = Instruction::decode(bytes)?;
+        let mut operands = [0; 3];
+        for (slot, operand) in operands.iter_mut().zip(&instruction.operands) {
+            *slot = match operand {
+                Operand::Reg(register) | Operand::FReg(register) => u16::from(*register),
+                Operand::Imm16(value) => *value,
+                // floats are kept as their half precision bits
+                Operand::Float(value) => f16::from_f64(*value).to_bits(),
+                Operand::Offset(value) => *value as u16,
+                Operand::Imm8(value) => *value as u8 as u16,
+                _ => unreachable!("decode only produces registers and 16 bit immediates"),
+            };
+        }
+        Ok(DecodedOp {
+            opcode: instruction.opcode,
+            operands,
+        })
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct VM {
-    pub registers: [i32; 32],
+    pub registers: [i32; REGISTER_COUNT],
+    pub float_registers: [f64; REGISTER_COUNT],
     // program counter, track which byte is executing
     pc: usize,
-    pub program: Vec<u8>,
-    remainder: u32,
+    program: Vec<u8>,
+    // whether to decode the whole program up front, see `set_predecode`
+    predecode: bool,
+    // the program decoded at each instruction boundary, dropped whenever
+    // the program changes
+    decoded: Option<Vec<Result<DecodedOp, DecodeError>>>,
+    // remainder of the last DIV or DIVI, or the bits of the unsigned
+    // remainder of the last DIVU
+    remainder: i32,
     equal_flag: bool,
+    // fault that stopped the last executed instruction, if any
+    fault: Option<Fault>,
+    // whether the last executed instruction was HLT
+    halted: bool,
 }
 
 impl VM {
     pub fn run(&mut self) {
         let mut is_done = false;
         while !is_done {
-            is_done = self.execute_instruction();
+            is_done = self.run_once();
         }
     }
 
-    pub fn run_once(&mut self) {
-        self.execute_instruction();
+    /// Executes a single instruction, returning true once the VM has
+    /// halted, faulted or run off the end of the program.
+    pub fn run_once(&mut self) -> bool {
+        self.fault = None;
+        self.halted = false;
+        let start = self.pc;
+        match self.execute_instruction(start) {
+            Ok(is_done) => is_done,
+            Err(fault) => {
+                // a faulting instruction is skipped, so execution can resume
+                self.pc = start + INSTRUCTION_SIZE;
+                self.fault = Some(fault);
+                true
+            }
+        }
     }
 
-    fn execute_instruction(&mut self) -> bool {
+    fn execute_instruction(&mut self, start: usize) -> Result<bool, Fault> {
         if self.pc >= self.program.len() {
-            return true;
+            return Ok(true);
         }
+        let op = self.decoded_op(start).map_err(|e| match e {
+            DecodeError::Truncated => Fault::TruncatedInstruction { pc: start },
+            DecodeError::IllegalOpcode { opcode } => Fault::IllegalOpcode { pc: start, opcode },
+        })?;
 
-        match self.decode_opcode() {
+        // jumps replace the next pc
+        let mut next = start + INSTRUCTION_SIZE;
+        let operands = &op.operands;
+        match op.opcode {
             Opcode::LOAD => {
-                let register = self.next_8_bits() as usize;
-                let number = self.next_16_bits() as u16;
-                self.registers[register] = i32::from(number);
+                let register = self.register(operands, 0, start)?;
+                self.registers[register] = i32::from(operands[1]);
             }
             Opcode::ADD => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 + register2;
+                let (register1, register2, target) = self.operation(operands, start)?;
+                self.registers[target] = register1.wrapping_add(register2);
             }
             Opcode::SUB => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 - register2;
+                let (register1, register2, target) = self.operation(operands, start)?;
+                self.registers[target] = register1.wrapping_sub(register2);
             }
             Opcode::MUL => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 * register2;
+                let (register1, register2, target) = self.operation(operands, start)?;
+                self.registers[target] = register1.wrapping_mul(register2);
             }
             Opcode::DIV => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 / register2;
-                self.remainder = (register1 % register2) as u32;
+                let (register1, register2, target) = self.operation(operands, start)?;
+                if register2 == 0 {
+                    return Err(Fault::DivideByZero { pc: start });
+                }
+                self.registers[target] = register1.wrapping_div(register2);
+                self.remainder = register1.wrapping_rem(register2);
             }
             Opcode::HLT => {
-                println!("HLT encountered");
-                return true;
+                self.halted = true;
             }
             Opcode::JMP => {
-                let target = self.registers[self.next_8_bits() as usize];
-                self.pc = target as usize;
+                let target = self.register_value(operands, 0, start)?;
+                next = self.absolute_target(start, next, i64::from(target))?;
             }
             Opcode::JMPF => {
-                let value = self.registers[self.next_8_bits() as usize];
-                self.pc += value as usize;
+                let offset = self.register_value(operands, 0, start)?;
+                next = self.relative_target(start, next, i64::from(offset), offset)?;
             }
             Opcode::JMPB => {
-                let value = self.registers[self.next_8_bits() as usize];
-                self.pc -= value as usize;
+                let offset = self.register_value(operands, 0, start)?;
+                next = self.relative_target(start, next, -i64::from(offset), offset)?;
             }
             Opcode::EQ => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-
+                let (register1, register2) = self.comparison(operands, start)?;
                 self.equal_flag = register1 == register2;
-                self.next_8_bits();
             }
             Opcode::NEQ => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-
+                let (register1, register2) = self.comparison(operands, start)?;
                 self.equal_flag = register1 != register2;
-                self.next_8_bits();
             }
             Opcode::GTE => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-
+                let (register1, register2) = self.comparison(operands, start)?;
                 self.equal_flag = register1 >= register2;
-                self.next_8_bits();
             }
             Opcode::LTE => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-
+                let (register1, register2) = self.comparison(operands, start)?;
                 self.equal_flag = register1 <= register2;
-                self.next_8_bits();
             }
             Opcode::LT => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-
+                let (register1, register2) = self.comparison(operands, start)?;
                 self.equal_flag = register1 < register2;
-                self.next_8_bits();
             }
             Opcode::GT => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-
+                let (register1, register2) = self.comparison(operands, start)?;
                 self.equal_flag = register1 > register2;
-                self.next_8_bits();
             }
             Opcode::JMPE => {
-                let target = self.registers[self.next_8_bits() as usize];
+                let target = self.register_value(operands, 0, start)?;
                 if self.equal_flag {
-                    self.pc = target as usize;
+                    next = self.absolute_target(start, next, i64::from(target))?;
                 }
             }
-            _ => {
-                println!("unrecognized opcode found! Terminating!");
-                return true;
+            Opcode::LOADF64 => {
+                let register = self.float_register(operands, 0, start)?;
+                self.float_registers[register] = f16::from_bits(operands[1]).to_f64();
+            }
+            Opcode::LOADF64W => {
+                let register = self.float_register(operands, 0, start)?;
+                let bits = self.float_registers[register].to_bits() << 16;
+                self.float_registers[register] = f64::from_bits(bits | u64::from(operands[1]));
+            }
+            Opcode::ADDF64 => {
+                let (register1, register2, target) = self.float_operation(operands, start)?;
+                self.float_registers[target] = register1 + register2;
+            }
+            Opcode::SUBF64 => {
+                let (register1, register2, target) = self.float_operation(operands, start)?;
+                self.float_registers[target] = register1 - register2;
+            }
+            Opcode::MULF64 => {
+                let (register1, register2, target) = self.float_operation(operands, start)?;
+                self.float_registers[target] = register1 * register2;
+            }
+            Opcode::DIVF64 => {
+                // division by zero gives an infinity or NaN rather than a fault
+                let (register1, register2, target) = self.float_operation(operands, start)?;
+                self.float_registers[target] = register1 / register2;
+            }
+            Opcode::EQF64 => {
+                let (register1, register2) = self.float_comparison(operands, start)?;
+                self.equal_flag = register1 == register2;
+            }
+            Opcode::NEQF64 => {
+                let (register1, register2) = self.float_comparison(operands, start)?;
+                self.equal_flag = register1 != register2;
+            }
+            Opcode::GTF64 => {
+                let (register1, register2) = self.float_comparison(operands, start)?;
+                self.equal_flag = register1 > register2;
+            }
+            Opcode::GTEF64 => {
+                let (register1, register2) = self.float_comparison(operands, start)?;
+                self.equal_flag = register1 >= register2;
+            }
+            Opcode::LTF64 => {
+                let (register1, register2) = self.float_comparison(operands, start)?;
+                self.equal_flag = register1 < register2;
+            }
+            Opcode::LTEF64 => {
+                let (register1, register2) = self.float_comparison(operands, start)?;
+                self.equal_flag = register1 <= register2;
+            }
+            Opcode::ITOF => {
+                let value = self.register_value(operands, 0, start)?;
+                let target = self.float_register(operands, 1, start)?;
+                self.float_registers[target] = f64::from(value);
+            }
+            Opcode::FTOI => {
+                let value = self.float_register_value(operands, 0, start)?;
+                let target = self.register(operands, 1, start)?;
+                // `as` rounds toward zero, saturates and turns NaN into 0
+                self.registers[target] = value as i32;
+            }
+            Opcode::DIVU => {
+                let (register1, register2, target) = self.unsigned_operation(operands, start)?;
+                if register2 == 0 {
+                    return Err(Fault::DivideByZero { pc: start });
+                }
+                self.registers[target] = (register1 / register2) as i32;
+                self.remainder = (register1 % register2) as i32;
+            }
+            Opcode::REMU => {
+                let (register1, register2, target) = self.unsigned_operation(operands, start)?;
+                if register2 == 0 {
+                    return Err(Fault::DivideByZero { pc: start });
+                }
+                self.registers[target] = (register1 % register2) as i32;
+            }
+            Opcode::MFREM => {
+                let target = self.register(operands, 0, start)?;
+                self.registers[target] = self.remainder;
+            }
+            Opcode::GTU => {
+                let (register1, register2) = self.unsigned_comparison(operands, start)?;
+                self.equal_flag = register1 > register2;
+            }
+            Opcode::GTEU => {
+                let (register1, register2) = self.unsigned_comparison(operands, start)?;
+                self.equal_flag = register1 >= register2;
+            }
+            Opcode::LTU => {
+                let (register1, register2) = self.unsigned_comparison(operands, start)?;
+                self.equal_flag = register1 < register2;
+            }
+            Opcode::LTEU => {
+                let (register1, register2) = self.unsigned_comparison(operands, start)?;
+                self.equal_flag = register1 <= register2;
+            }
+            Opcode::JMPR => {
+                let offset = i32::from(operands[0] as i16);
+                next = self.relative_target(start, next, i64::from(offset), offset)?;
+            }
+            Opcode::JMPRE => {
+                let offset = i32::from(operands[0] as i16);
+                if self.equal_flag {
+                    next = self.relative_target(start, next, i64::from(offset), offset)?;
+                }
             }
+            Opcode::ADDI => {
+                let (register, value, target) = self.immediate_operation(operands, start)?;
+                self.registers[target] = register.wrapping_add(value);
+            }
+            Opcode::SUBI => {
+                let (register, value, target) = self.immediate_operation(operands, start)?;
+                self.registers[target] = register.wrapping_sub(value);
+            }
+            Opcode::MULI => {
+                let (register, value, target) = self.immediate_operation(operands, start)?;
+                self.registers[target] = register.wrapping_mul(value);
+            }
+            Opcode::DIVI => {
+                let (register, value, target) = self.immediate_operation(operands, start)?;
+                if value == 0 {
+                    return Err(Fault::DivideByZero { pc: start });
+                }
+                self.registers[target] = register.wrapping_div(value);
+                self.remainder = register.wrapping_rem(value);
+            }
+            Opcode::JMPI => next = self.absolute_target(start, next, i64::from(operands[0]))?,
+            Opcode::JMPEI => {
+                if self.equal_flag {
+                    next = self.absolute_target(start, next, i64::from(operands[0]))?;
+                }
+            }
+            Opcode::MOV => {
+                let target = self.register(operands, 0, start)?;
+                self.registers[target] = self.register_value(operands, 1, start)?;
+            }
+            Opcode::INC => {
+                let register = self.register(operands, 0, start)?;
+                self.registers[register] = self.registers[register].wrapping_add(1);
+            }
+            Opcode::DEC => {
+                let register = self.register(operands, 0, start)?;
+                self.registers[register] = self.registers[register].wrapping_sub(1);
+            }
+            Opcode::NEG => {
+                let register = self.register(operands, 0, start)?;
+                self.registers[register] = self.registers[register].wrapping_neg();
+            }
+            Opcode::IGL => unreachable!("decode rejects illegal opcodes"),
+        }
+
+        self.pc = next;
+        Ok(self.halted)
+    }
+
+    fn decoded_op(&mut self, start: usize) -> Result<DecodedOp, DecodeError> {
+        if self.predecode && self.decoded.is_none() {
+            self.decoded = Some(
+                self.program
+                    .chunks(INSTRUCTION_SIZE)
+                    .map(DecodedOp::decode)
+                    .collect(),
+            );
+        }
+        match &self.decoded {
+            // jumps may land between instruction boundaries, which aren't cached
+            Some(ops) if start.is_multiple_of(INSTRUCTION_SIZE) => ops[start / INSTRUCTION_SIZE],
+            _ => DecodedOp::decode(&self.program[start..]),
+        }
+    }
+
+    // register number of operand `index`
+    fn register(&self, operands: &[u16], index: usize, start: usize) -> Result<usize, Fault> {
+        // register operands are a single byte
+        let register = operands[index] as u8;
+        if usize::from(register) >= self.registers.len() {
+            return Err(Fault::InvalidRegister {
+                pc: start,
+                register,
+            });
+        }
+        Ok(usize::from(register))
+    }
+
+    fn register_value(&self, operands: &[u16], index: usize, start: usize) -> Result<i32, Fault> {
+        let register = self.register(operands, index, start)?;
+        Ok(self.registers[register])
+    }
+
+    // operands of a `op $a $b $target` arithmetic instruction
+    fn operation(&self, operands: &[u16], start: usize) -> Result<(i32, i32, usize), Fault> {
+        let register1 = self.register_value(operands, 0, start)?;
+        let register2 = self.register_value(operands, 1, start)?;
+        let target = self.register(operands, 2, start)?;
+        Ok((register1, register2, target))
+    }
+
+    // operands of a `op $a $b` comparison
+    fn comparison(&self, operands: &[u16], start: usize) -> Result<(i32, i32), Fault> {
+        let register1 = self.register_value(operands, 0, start)?;
+        let register2 = self.register_value(operands, 1, start)?;
+        Ok((register1, register2))
+    }
+
+    // operands of a `op $a $target #n` instruction with a one byte immediate
+    fn immediate_operation(
+        &self,
+        operands: &[u16],
+        start: usize,
+    ) -> Result<(i32, i32, usize), Fault> {
+        let register = self.register_value(operands, 0, start)?;
+        let target = self.register(operands, 1, start)?;
+        let value = i32::from(operands[2] as u8 as i8);
+        Ok((register, value, target))
+    }
+
+    // `operation` with both values read as unsigned
+    fn unsigned_operation(
+        &self,
+        operands: &[u16],
+        start: usize,
+    ) -> Result<(u32, u32, usize), Fault> {
+        let (register1, register2, target) = self.operation(operands, start)?;
+        Ok((register1 as u32, register2 as u32, target))
+    }
+
+    // `comparison` with both values read as unsigned
+    fn unsigned_comparison(&self, operands: &[u16], start: usize) -> Result<(u32, u32), Fault> {
+        let (register1, register2) = self.comparison(operands, start)?;
+        Ok((register1 as u32, register2 as u32))
+    }
+
+    fn float_register(&self, operands: &[u16], index: usize, start: usize) -> Result<usize, Fault> {
+        let register = operands[index] as u8;
+        if usize::from(register) >= self.float_registers.len() {
+            return Err(Fault::InvalidFloatRegister {
+                pc: start,
+                register,
+            });
         }
+        Ok(usize::from(register))
+    }
 
-        false
+    fn float_register_value(
+        &self,
+        operands: &[u16],
+        index: usize,
+        start: usize,
+    ) -> Result<f64, Fault> {
+        let register = self.float_register(operands, index, start)?;
+        Ok(self.float_registers[register])
     }
 
-    fn decode_opcode(&mut self) -> Opcode {
-        let opcode = Opcode::from(self.program[self.pc]);
-        self.pc += 1;
-        opcode
+    // operands of a `op $fa $fb $ftarget` arithmetic instruction
+    fn float_operation(&self, operands: &[u16], start: usize) -> Result<(f64, f64, usize), Fault> {
+        let register1 = self.float_register_value(operands, 0, start)?;
+        let register2 = self.float_register_value(operands, 1, start)?;
+        let target = self.float_register(operands, 2, start)?;
+        Ok((register1, register2, target))
     }
 
-    fn next_8_bits(&mut self) -> u8 {
-        let result = self.program[self.pc];
-        self.pc += 1;
-        result
+    // operands of a `op $fa $fb` comparison
+    fn float_comparison(&self, operands: &[u16], start: usize) -> Result<(f64, f64), Fault> {
+        let register1 = self.float_register_value(operands, 0, start)?;
+        let register2 = self.float_register_value(operands, 1, start)?;
+        Ok((register1, register2))
     }
 
-    fn next_16_bits(&mut self) -> u16 {
-        let first_8_bits = u16::from(self.program[self.pc]);
-        let next_8_bits = u16::from(self.program[self.pc + 1]);
-        let result = (first_8_bits << 8) | next_8_bits;
-        self.pc += 2;
-        result
+    // target of a relative jump by `distance` bytes from `end`, the offset
+    // just past the jump instruction, so a distance of 0 falls through. The
+    // target must lie within the program, or be its very end.
+    fn relative_target(
+        &self,
+        start: usize,
+        end: usize,
+        distance: i64,
+        offset: i32,
+    ) -> Result<usize, Fault> {
+        let target = end as i64 + distance;
+        if target < 0 || target > self.program.len() as i64 {
+            return Err(Fault::InvalidJumpTarget { pc: start, offset });
+        }
+        Ok(target as usize)
+    }
+
+    // `target` of a jump to a fixed address, checked like a relative jump
+    // from `end`, so a fault gives the distance
+    fn absolute_target(&self, start: usize, end: usize, target: i64) -> Result<usize, Fault> {
+        let distance = target - end as i64;
+        self.relative_target(start, end, distance, distance as i32)
     }
 
     pub fn add_byte(&mut self, b: u8) {
         self.program.push(b);
+        self.decoded = None;
+    }
+
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        self.program.extend_from_slice(bytes);
+        self.decoded = None;
+    }
+
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    /// Decodes the whole program before running it rather than each
+    /// instruction as it executes, which is faster for long loops. The
+    /// decoded program is rebuilt after any change to the program.
+    pub fn set_predecode(&mut self, predecode: bool) {
+        self.predecode = predecode;
+        self.decoded = None;
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn equal_flag(&self) -> bool {
+        self.equal_flag
+    }
+
+    pub fn remainder(&self) -> i32 {
+        self.remainder
+    }
+
+    pub fn fault(&self) -> Option<&Fault> {
+        self.fault.as_ref()
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn clear_program(&mut self) {
+        self.program.clear();
+        self.decoded = None;
+        self.pc = 0;
     }
 }
 
@@ -163,6 +564,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn test_opcode_load() {
         let mut test_vm = VM::default();
         test_vm.program = vec![0, 0, 1, 244];
@@ -172,6 +574,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_opcode_add() {
         let mut test_vm = VM::default();
         let load1_bytes = vec![0, 0, 0, 100];
@@ -191,6 +594,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_opcode_sub() {
         let mut test_vm = VM::default();
         let load1_bytes = vec![0, 0, 0, 200];
@@ -241,7 +645,8 @@ mod test {
         test_vm.program = test_bytes;
 
         test_vm.run();
-        assert_eq!(test_vm.pc, 1)
+        assert_eq!(test_vm.pc, 4);
+        assert!(test_vm.halted());
     }
 
     #[test]
@@ -256,19 +661,21 @@ mod test {
     #[test]
     fn test_opcode_jmpf() {
         let mut test_vm = VM::default();
-        test_vm.registers[0] = 2;
-        let test_bytes = vec![7, 0, 0, 0, 200, 0, 0, 0];
+        test_vm.registers[0] = 4;
+        let test_bytes = vec![7, 0, 0, 0, 200, 0, 0, 0, 200, 0, 0, 0];
         test_vm.program = test_bytes;
 
         test_vm.run_once();
-        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.pc, 8);
     }
 
     #[test]
     fn test_opcode_jmpb() {
-        let mut test_vm = VM::default();
-        test_vm.pc = 4;
-        test_vm.registers[0] = 6;
+        let mut test_vm = VM {
+            pc: 4,
+            ..VM::default()
+        };
+        test_vm.registers[0] = 8;
         let test_bytes = vec![200, 0, 0, 0, 8, 0, 0, 0];
         test_vm.program = test_bytes;
 
@@ -277,6 +684,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_opcode_eq() {
         let mut test_vm = VM::default();
         test_vm.registers[0] = 4;
@@ -289,6 +697,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_opcode_neq() {
         let mut test_vm = VM::default();
         test_vm.registers[0] = 4;
@@ -301,6 +710,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_opcode_gte() {
         let mut test_vm = VM::default();
         test_vm.registers[0] = 5;
@@ -313,6 +723,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_opcode_lte() {
         let mut test_vm = VM::default();
         test_vm.registers[0] = 3;
@@ -325,6 +736,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_opcode_lt() {
         let mut test_vm = VM::default();
         test_vm.registers[0] = 4;
@@ -337,6 +749,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_opcode_gt() {
         let mut test_vm = VM::default();
         test_vm.registers[0] = 4;
@@ -367,6 +780,389 @@ mod test {
         test_vm.program = test_bytes;
 
         test_vm.run();
-        assert_eq!(test_vm.pc, 1);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_instructions_are_fixed_width() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = 12;
+        test_vm.program = vec![9, 0, 0, 0, 15, 0, 0, 0, 0, 1, 0, 1, 5, 0, 0, 0];
+
+        assert!(!test_vm.run_once());
+        assert_eq!(test_vm.pc, 4);
+        test_vm.run();
+        assert_eq!(test_vm.registers[1], 0);
+        assert_eq!(test_vm.pc, 16);
+        assert!(test_vm.halted());
+    }
+
+    #[test]
+    fn test_fault_divide_by_zero() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = 10;
+        test_vm.program = vec![4, 0, 1, 2];
+
+        test_vm.run();
+        assert_eq!(test_vm.fault(), Some(&Fault::DivideByZero { pc: 0 }));
+        assert_eq!(test_vm.registers[2], 0);
+    }
+
+    #[test]
+    fn test_fault_invalid_register() {
+        let mut test_vm = VM {
+            program: vec![0, 0, 0, 1, 0, 32, 0, 1],
+            ..VM::default()
+        };
+
+        test_vm.run();
+        assert_eq!(test_vm.registers[0], 1);
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidRegister {
+                pc: 4,
+                register: 32
+            })
+        );
+    }
+
+    #[test]
+    fn test_fault_truncated_instruction() {
+        let mut test_vm = VM {
+            program: vec![0, 0, 1],
+            ..VM::default()
+        };
+
+        assert!(test_vm.run_once());
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::TruncatedInstruction { pc: 0 })
+        );
+    }
+
+    #[test]
+    fn test_fault_jmpb_underflow() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = 6;
+        test_vm.program = vec![8, 0, 0, 0];
+
+        assert!(test_vm.run_once());
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidJumpTarget { pc: 0, offset: 6 })
+        );
+    }
+
+    #[test]
+    fn test_fault_jmp_out_of_range() {
+        // jmp $0, eq $0 $0, jmpe $1
+        let mut test_vm = VM {
+            program: vec![6, 0, 0, 0, 9, 0, 0, 0, 15, 1, 0, 0],
+            ..VM::default()
+        };
+        test_vm.registers[0] = -4;
+        assert!(test_vm.run_once());
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidJumpTarget { pc: 0, offset: -8 })
+        );
+
+        test_vm.registers[0] = 12;
+        test_vm.pc = 0;
+        assert!(!test_vm.run_once());
+        assert_eq!(test_vm.pc, 12);
+
+        test_vm.registers[1] = 100;
+        test_vm.pc = 4;
+        test_vm.run_once();
+        assert!(test_vm.run_once());
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidJumpTarget { pc: 8, offset: 88 })
+        );
+        assert_eq!(test_vm.pc, 12);
+    }
+
+    #[test]
+    fn test_fault_cleared_on_next_instruction() {
+        let mut test_vm = VM {
+            program: vec![200, 0, 0, 0, 0, 0, 1, 244],
+            ..VM::default()
+        };
+
+        assert!(test_vm.run_once());
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::IllegalOpcode { pc: 0, opcode: 200 })
+        );
+        assert!(!test_vm.run_once());
+        assert_eq!(test_vm.fault(), None);
+        assert_eq!(test_vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_predecode() {
+        let mut test_vm = VM::default();
+        test_vm.set_predecode(true);
+        test_vm.registers[0] = 12;
+        test_vm.add_bytes(&[0, 1, 0, 7, 6, 0, 0, 0, 200, 0, 0, 0]);
+        assert!(!test_vm.run_once());
+        assert!(test_vm.decoded.is_some());
+
+        // the jump lands on bytes added after the program was decoded
+        test_vm.add_bytes(&[0, 2, 0, 9]);
+        assert!(test_vm.decoded.is_none());
+        test_vm.run();
+        assert_eq!(test_vm.registers[1], 7);
+        assert_eq!(test_vm.registers[2], 9);
+        assert_eq!(test_vm.fault(), None);
+
+        // unaligned jump targets are decoded as they're reached
+        test_vm.registers[0] = 2;
+        test_vm.pc = 4;
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 2);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[7], 0x0600);
+    }
+
+    #[test]
+    fn test_float_instructions() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = 7;
+        test_vm.program = vec![
+            16, 0, 0x3e, 0x00, // loadf64 $f0 #1.5
+            27, 0, 1, 0, // itof $0 $f1
+            19, 0, 1, 2, // mulf64 $f0 $f1 $f2
+            20, 2, 0, 3, // divf64 $f2 $f0 $f3
+            18, 2, 1, 4, // subf64 $f2 $f1 $f4
+            28, 4, 5, 0, // ftoi $f4 $5
+            23, 2, 3, 0, // gtf64 $f2 $f3
+            48, 6, 0x3f, 0xb9, // loadf64w $f6 #16313
+            48, 6, 0x99, 0x99, // loadf64w $f6 #39321
+            48, 6, 0x99, 0x99, // loadf64w $f6 #39321
+            48, 6, 0x99, 0x9a, // loadf64w $f6 #39322
+        ];
+
+        test_vm.run();
+        assert_eq!(test_vm.fault(), None);
+        assert_eq!(test_vm.float_registers[0], 1.5);
+        assert_eq!(test_vm.float_registers[1], 7.0);
+        assert_eq!(test_vm.float_registers[2], 10.5);
+        assert_eq!(test_vm.float_registers[3], 7.0);
+        assert_eq!(test_vm.float_registers[4], 3.5);
+        assert_eq!(test_vm.registers[5], 3);
+        assert!(test_vm.equal_flag);
+        assert_eq!(test_vm.float_registers[6], 0.1);
+    }
+
+    #[test]
+    fn test_float_edge_cases() {
+        let mut test_vm = VM::default();
+        test_vm.float_registers[0] = 1.0;
+        test_vm.float_registers[1] = f64::NAN;
+        test_vm.program = vec![
+            20, 0, 2, 3, // divf64 $f0 $f2 $f3
+            28, 3, 0, 0, // ftoi $f3 $0
+            28, 1, 1, 0, // ftoi $f1 $1
+            21, 1, 1, 0, // eqf64 $f1 $f1
+            17, 0, 32, 0, // addf64 $f0 $f32 $f0
+        ];
+
+        test_vm.run();
+        assert_eq!(test_vm.float_registers[3], f64::INFINITY);
+        assert_eq!(test_vm.registers[0], i32::MAX);
+        assert_eq!(test_vm.registers[1], 0);
+        assert!(!test_vm.equal_flag);
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidFloatRegister {
+                pc: 16,
+                register: 32
+            })
+        );
+    }
+
+    #[test]
+    fn test_divi_remainder() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = -7;
+        test_vm.program = vec![
+            41, 0, 1, 4, // divi $0 $1 #4
+            31, 2, 0, 0, // mfrem $2
+        ];
+
+        test_vm.run();
+        assert_eq!(test_vm.registers[1], -1);
+        assert_eq!(test_vm.registers[2], -3);
+    }
+
+    #[test]
+    fn test_unsigned_division() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = -7;
+        test_vm.registers[1] = 2;
+        test_vm.program = vec![
+            4, 0, 1, 2, // div $0 $1 $2
+            31, 3, 0, 0, // mfrem $3
+            29, 0, 1, 4, // divu $0 $1 $4
+            31, 5, 0, 0, // mfrem $5
+            30, 0, 1, 6, // remu $0 $1 $6
+        ];
+
+        test_vm.run();
+        assert_eq!(test_vm.registers[2], -3);
+        assert_eq!(test_vm.registers[3], -1);
+        assert_eq!(test_vm.registers[4], 0x7fff_fffc);
+        assert_eq!(test_vm.registers[5], 1);
+        assert_eq!(test_vm.registers[6], 1);
+        assert_eq!(test_vm.remainder(), 1);
+
+        test_vm.program = vec![30, 0, 7, 6];
+        test_vm.pc = 0;
+        test_vm.run();
+        assert_eq!(test_vm.fault(), Some(&Fault::DivideByZero { pc: 0 }));
+    }
+
+    #[test]
+    fn test_unsigned_comparisons() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = -1;
+        test_vm.registers[1] = 1;
+        for (opcode, expected) in [(32, true), (33, true), (34, false), (35, false)] {
+            test_vm.program = vec![opcode, 0, 1, 0];
+            test_vm.pc = 0;
+            test_vm.run_once();
+            assert_eq!(test_vm.equal_flag, expected, "opcode {}", opcode);
+        }
+        // the signed comparison disagrees
+        test_vm.program = vec![14, 0, 1, 0];
+        test_vm.pc = 0;
+        test_vm.run_once();
+        assert!(!test_vm.equal_flag);
+    }
+
+    #[test]
+    fn test_signed_relative_jumps() {
+        // hlt, jmpf $0, jmpb $0
+        let mut test_vm = VM {
+            program: vec![5, 0, 0, 0, 7, 0, 0, 0, 8, 0, 0, 0],
+            ..VM::default()
+        };
+
+        test_vm.registers[0] = -8;
+        test_vm.pc = 4;
+        assert!(!test_vm.run_once());
+        assert_eq!(test_vm.pc, 0);
+
+        test_vm.registers[0] = 12;
+        test_vm.pc = 8;
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 0);
+
+        // the very end of the program is a valid target
+        test_vm.registers[0] = 0;
+        test_vm.pc = 8;
+        test_vm.run_once();
+        assert_eq!(test_vm.fault(), None);
+        assert_eq!(test_vm.pc, 12);
+
+        test_vm.registers[0] = 8;
+        test_vm.pc = 4;
+        assert!(test_vm.run_once());
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidJumpTarget { pc: 4, offset: 8 })
+        );
+    }
+
+    #[test]
+    fn test_opcode_jmpr() {
+        // jmpr #-8, then jmpre #4 with the flag clear and set
+        let mut test_vm = VM {
+            program: vec![5, 0, 0, 0, 36, 255, 248, 0, 37, 0, 4, 0, 5, 0, 0, 0],
+            pc: 4,
+            ..VM::default()
+        };
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 0);
+
+        test_vm.pc = 8;
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 12);
+        test_vm.equal_flag = true;
+        test_vm.pc = 8;
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 16);
+
+        test_vm.program = vec![36, 127, 255, 0];
+        test_vm.pc = 0;
+        assert!(test_vm.run_once());
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidJumpTarget {
+                pc: 0,
+                offset: 32767
+            })
+        );
+    }
+
+    #[test]
+    fn test_immediate_instructions() {
+        let mut test_vm = VM::default();
+        test_vm.registers[0] = 10;
+        test_vm.program = vec![
+            38, 0, 1, 254, // addi $0 $1 #-2
+            39, 0, 2, 5, // subi $0 $2 #5
+            40, 0, 3, 3, // muli $0 $3 #3
+            41, 0, 4, 3, // divi $0 $4 #3
+            42, 0, 24, 0, // jmpi #24
+            5, 0, 0, 0, // hlt
+            9, 0, 0, 0, // eq $0 $0
+            43, 0, 20, 0, // jmpei #20
+        ];
+
+        test_vm.run();
+        assert_eq!(test_vm.registers[1], 8);
+        assert_eq!(test_vm.registers[2], 5);
+        assert_eq!(test_vm.registers[3], 30);
+        assert_eq!(test_vm.registers[4], 3);
+        assert_eq!(test_vm.remainder(), 1);
+        assert!(test_vm.halted());
+        assert_eq!(test_vm.pc, 24);
+
+        test_vm.program = vec![41, 0, 1, 0];
+        test_vm.pc = 0;
+        test_vm.run();
+        assert_eq!(test_vm.fault(), Some(&Fault::DivideByZero { pc: 0 }));
+
+        test_vm.program = vec![9, 0, 0, 0, 43, 0, 100, 0];
+        test_vm.pc = 0;
+        test_vm.run();
+        assert_eq!(
+            test_vm.fault(),
+            Some(&Fault::InvalidJumpTarget { pc: 4, offset: 92 })
+        );
+    }
+
+    #[test]
+    fn test_register_instructions() {
+        let mut test_vm = VM::default();
+        test_vm.registers[1] = 41;
+        test_vm.registers[3] = i32::MIN;
+        test_vm.program = vec![
+            44, 0, 1, 0, // mov $0 $1
+            45, 0, 0, 0, // inc $0
+            46, 1, 0, 0, // dec $1
+            47, 1, 0, 0, // neg $1
+            47, 3, 0, 0, // neg $3
+            46, 3, 0, 0, // dec $3
+        ];
+
+        test_vm.run();
+        assert_eq!(test_vm.registers[0], 42);
+        assert_eq!(test_vm.registers[1], -40);
+        assert_eq!(test_vm.registers[3], i32::MAX);
+        assert_eq!(test_vm.fault(), None);
     }
 }
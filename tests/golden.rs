@@ -0,0 +1,30 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// runs every script in tests/scripts through `alvm <script>`, which exits
+// non-zero when an `.expect` check or any other command fails
+#[test]
+fn test_golden_scripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+    let mut scripts: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "alvm"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    for script in scripts {
+        let output = Command::new(env!("CARGO_BIN_EXE_alvm"))
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{} failed:\n{}",
+            script.display(),
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+}